@@ -1,28 +1,242 @@
-use egui::{text::LayoutJob, vec2, Align, Align2, FontSelection, Frame, InnerResponse, Margin, RichText, Stroke, Style, Widget};
+use egui::{text::LayoutJob, vec2, Align, Align2, FontSelection, Frame, InnerResponse, Key, KeyboardShortcut, Margin, Modifiers, RichText, Stroke, Style, Widget};
 use egui_flex::{item, Flex, FlexAlign, FlexJustify};
-use egui_material_icons::{icon_button, icons::{ICON_ADD, ICON_CIRCLE, ICON_DELETE, ICON_JOIN, ICON_POWER, ICON_POWER_OFF, ICON_UNDO, ICON_VOLUME_OFF, ICON_VOLUME_UP}};
+use egui_material_icons::{icon_button, icons::{ICON_ADD, ICON_CIRCLE, ICON_CLOSE, ICON_CONTENT_COPY, ICON_DELETE, ICON_ERROR, ICON_GRID_ON, ICON_HEADPHONES, ICON_JOIN, ICON_PLAY_ARROW, ICON_POWER, ICON_POWER_OFF, ICON_SAVE, ICON_TUNE, ICON_UNDO, ICON_VIEW_AGENDA, ICON_VOLUME_OFF, ICON_VOLUME_UP, ICON_WARNING}};
 
-use crate::{device::{Device, EnumIndex}, state::{MixerDestination, MixerEntry, MixerOutput}, theme};
+use crate::{fader::Fader, dbus::DbusService, device::{audible, routes, soloed_mixes, CardWatcher, Device, DeviceError, DeviceInfo, DeviceState}, history::History, matrix, meter::{self, Meters}, midi::{self, Mapping, Midi, Target}, profile::{DeviceProfile, PROFILES}, osc::{self, OscServer}, schema, session::SessionFile, state::{Id, MixerDestination, MixerEntry, MixerOutput, PanLaw, Port, Scene}, theme};
 
-#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, PartialEq)]
 #[serde(default)]
 pub struct AppState {
-    pub capture: /*[Option<EnumIndex>; 18]*/ Vec<Option<EnumIndex>>,
+    pub capture: /*[Option<EnumIndex>; 18]*/ Vec<Option<Port>>,
     pub mixer_entries: Vec<MixerEntry>,
     pub global_gain: f32,
     pub global_mute: bool,
-    pub hi_z_1: bool,
-    pub hi_z_2: bool,
-    pub outputs: [MixerOutput; 3]
+    // one per input with the switch, in the order the device profile lists them
+    pub hi_z: Vec<bool>,
+    pub pad: Vec<bool>,
+    pub air: Vec<bool>,
+    pub outputs: Vec<MixerOutput>,
+    pub pan_law: PanLaw
+}
+
+impl AppState {
+    // make a state (possibly saved for another model) line up with the device's inputs and outputs
+    pub fn fit(&mut self, device: &Device) {
+        let p = device.profile;
+        self.capture.resize(p.capture_channels, None);
+        self.hi_z.resize(p.hi_z.len(), false);
+        self.pad.resize(p.pad.len(), false);
+        self.air.resize(p.air.len(), false);
+        self.outputs.truncate(p.outputs.len());
+        for (i, name) in p.outputs.iter().enumerate() {
+            if i == self.outputs.len() {
+                self.outputs.push(MixerOutput {
+                    source: (Port::at(&device.audio_sources, i * 2), Port::at(&device.audio_sources, i * 2 + 1)),
+                    ..Default::default()
+                });
+            }
+            self.outputs[i].name = (*name).to_owned();
+        }
+    }
+
+    // names of the ports in use that `device` doesn't have, eg. after switching to a model without them
+    pub fn unresolved(&self, device: &Device) -> Vec<String> {
+        let sources = self.capture.iter().flatten()
+            .chain(self.mixer_entries.iter().flat_map(|e| [Some(&e.source), e.stereo.then_some(&e.source_r)].into_iter().flatten()))
+            .chain(self.outputs.iter().flat_map(|o| [&o.source.0, &o.source.1]))
+            .filter(|p| p.resolve(&device.audio_sources).is_none());
+        let mixes = self.mixer_entries.iter()
+            .flat_map(|e| &e.dests)
+            .flat_map(|d| [Some(&d.dest), d.stereo.then_some(&d.dest_r)].into_iter().flatten())
+            .filter(|p| p.resolve(&device.mixer_destinations).is_none());
+        let mut names: Vec<String> = Vec::new();
+        for p in sources.chain(mixes) {
+            if !names.contains(&p.0) {
+                names.push(p.0.clone());
+            }
+        }
+        names
+    }
 }
 
 pub struct ScarlettControlApp {
     pub state: AppState,
-    pub device: Device
+    pub device: Device,
+    // state read from the device on startup, while the user decides whether to use it over the saved session
+    device_state: Option<AppState>,
+    // supported interfaces that are plugged in
+    devices: Vec<DeviceInfo>,
+    // the interface couldn't be used, so `device` is standing in for it and nothing reaches the hardware
+    offline: bool,
+    // the last thing that went wrong, shown until it's dismissed
+    error: Option<DeviceError>,
+    cards: CardWatcher,
+    // started without an interface, so use the first one that's plugged in
+    auto_connect: bool,
+    // saved snapshots of the state for this device
+    scenes: Vec<Scene>,
+    show_scenes: bool,
+    mixer_view: MixerView,
+    show_meters: bool,
+    // levels of the capture channels, while they're shown and the device is there to read them from
+    meters: Option<Meters>,
+    file_dialog: Option<FileDialog>,
+    history: History,
+    osc_enabled: bool,
+    osc_port: u16,
+    osc: Option<OscServer>,
+    // why the server couldn't be started on a port, which isn't tried again until the port changes
+    osc_error: Option<(u16, String)>,
+    midi: Midi,
+    dbus_enabled: bool,
+    dbus: Option<DbusService>,
+    // why the service couldn't be started, which isn't tried again until it's turned off and on
+    dbus_error: Option<String>
+}
+
+// picking a file to export the state to or import it from
+struct FileDialog {
+    import: bool,
+    path: String,
+    // what went wrong last time, or what couldn't be imported
+    message: Option<String>
+}
+
+impl FileDialog {
+    fn new(import: bool) -> Self {
+        let home = std::env::var("HOME").unwrap_or_default();
+        FileDialog {
+            import,
+            path: format!("{}/scarlett-session.toml", home),
+            message: None
+        }
+    }
+}
+
+// ways of showing the mixer entries
+#[derive(PartialEq, Clone, Copy)]
+enum MixerView {
+    Cards,
+    // a grid of matrix inputs and mixes
+    Matrix,
+    // a column of faders for each destination
+    Strips
+}
+
+enum SceneAction {
+    Recall(usize),
+    // overwrite with the current state
+    Store(usize),
+    Duplicate(usize),
+    Delete(usize)
 }
 
-fn capture_default(device: &Device) -> Vec<Option<EnumIndex>> {
-    (0..device.audio_sources.len()).map(|i| Some(i)).collect()
+// a device picked from the device menu
+enum DeviceChoice {
+    Hardware(DeviceInfo),
+    Simulated(&'static DeviceProfile)
+}
+
+const UNDO: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+const REDO: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::Z);
+
+// where eframe keeps the saved state
+pub const APP_ID: &str = "scarlett-control";
+
+// the last device used, and its profile so it can be waited for if it isn't plugged in
+const DEVICE_KEY: &str = "device";
+const PROFILE_KEY: &str = "profile";
+// whether the osc server is on, and its port
+const OSC_KEY: &str = "osc";
+// whether midi is on, and whether it sends feedback
+const MIDI_KEY: &str = "midi";
+// whether the d-bus service is on
+const DBUS_KEY: &str = "dbus";
+// whether the meters are shown
+const METERS_KEY: &str = "meters";
+
+// each device gets its own saved state, scenes and midi mappings
+pub fn state_key(device: &Device) -> String {
+    format!("state {}", device.id)
+}
+
+pub fn scenes_key(device: &Device) -> String {
+    format!("scenes {}", device.id)
+}
+
+fn mappings_key(device: &Device) -> String {
+    format!("midi {}", device.id)
+}
+
+fn load_mappings(storage: Option<&dyn eframe::Storage>, device: &Device) -> Vec<Mapping> {
+    storage.and_then(|storage| eframe::get_value(storage, &mappings_key(device))).unwrap_or_default()
+}
+
+fn load_scenes(storage: Option<&dyn eframe::Storage>, device: &Device) -> Vec<Scene> {
+    storage.and_then(|storage| schema::load_scenes(storage, &scenes_key(device), device))
+        .map(|(scenes, version)| {
+            if version != schema::VERSION {
+                schema::backup_storage(version);
+            }
+            scenes
+        })
+        .unwrap_or_default()
+}
+
+fn capture_default(device: &Device) -> Vec<Option<Port>> {
+    (0..device.profile.capture_channels).map(|i| Some(Port::at(&device.audio_sources, i))).collect()
+}
+
+fn watch(ctx: &egui::Context, device: &Device) {
+    let ctx = ctx.clone();
+    device.watch(move || ctx.request_repaint());
+}
+
+// the interfaces that are plugged in, and the first problem with any that couldn't be looked at
+fn list_devices() -> (Vec<DeviceInfo>, Option<DeviceError>) {
+    let mut error = None;
+    let devices = Device::list().into_iter()
+        .filter_map(|r| r.map_err(|e| {
+            error.get_or_insert(e);
+        }).ok())
+        .collect();
+    (devices, error)
+}
+
+// open `info`, or an offline stand-in for it and the reason why not
+fn open_device(info: &DeviceInfo) -> (Device, Option<DeviceError>) {
+    match Device::open(info) {
+        Ok(device) => (device, None),
+        Err(e) => (Device::offline(info.profile, info.id.clone()), Some(e))
+    }
+}
+
+// what the app last had on `device`, and the schema version it was saved with
+pub fn saved_state(storage: &dyn eframe::Storage, device: &Device) -> Option<(AppState, u32)> {
+    schema::load_state(storage, &state_key(device), device)
+        // sessions from before there was one per device
+        .or_else(|| schema::load_state(storage, eframe::APP_KEY, device))
+}
+
+// the saved state for `device`, and the state read from the device if that's different
+fn load_state(storage: Option<&dyn eframe::Storage>, device: &Device) -> (AppState, Option<AppState>) {
+    // what the interface is actually doing, which something else may have changed since we last ran
+    let hardware = device.known();
+    let saved = storage.and_then(|storage| saved_state(storage, device))
+        .map(|(mut s, version)| {
+            if version != schema::VERSION {
+                schema::backup_storage(version);
+            }
+            s.fit(device);
+            s
+        });
+    match saved {
+        Some(saved) => {
+            let differs = !hardware.agrees_with(&DeviceState::from_state(&saved, device));
+            (saved, differs.then(|| hardware.to_state(device)))
+        }
+        None => (hardware.to_state(device), None)
+    }
 }
 
 impl ScarlettControlApp {
@@ -34,38 +248,341 @@ impl ScarlettControlApp {
         cc.egui_ctx.add_font(theme::font());
         cc.egui_ctx.set_visuals(theme::visuals(cc.egui_ctx.style().visuals.clone()));
 
-        let device = Device::new().expect("Failed to connect to hardware device");
+        let (devices, list_error) = list_devices();
+        let last: Option<String> = cc.storage.and_then(|storage| eframe::get_value(storage, DEVICE_KEY));
+        let last_profile = cc.storage.and_then(|storage| eframe::get_value::<String>(storage, PROFILE_KEY))
+            .and_then(|name| DeviceProfile::find(&name));
+        let mut auto_connect = false;
 
-        let state = cc.storage.and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
-            .unwrap_or_else(|| {
-                let output = |name: &str| MixerOutput {
-                    name: name.to_owned(),
-                    gain: 0.0,
-                    mute: false,
-                    source: /*(AudioSource::MixA, AudioSource::MixB)*/ (0, 1),
-                    split: false
-                };
-        
-                AppState {
-                    capture: capture_default(&device) /*std::array::from_fn(|i| Some(AudioSource::VARIANTS[i]))*/,
-                    mixer_entries: Vec::new(),
-                    global_gain: 0.0,
-                    global_mute: false,
-                    hi_z_1: false,
-                    hi_z_2: false,
-                    outputs: [
-                        output("Monitor"),
-                        output("Headphone"),
-                        output("SPDIF")
-                    ]
-                }    
-            });
+        // SCARLETT_SIMULATE can name a model, eg. "Scarlett 18i20 Gen 2"
+        let (device, offline, open_error) = if let Some(model) = std::env::var_os("SCARLETT_SIMULATE") {
+            (Device::simulated(DeviceProfile::find(&model.to_string_lossy()).unwrap_or(DeviceProfile::default_profile())), false, None)
+        } else if let Some(info) = devices.iter().find(|d| Some(&d.id) == last.as_ref()).or(devices.first()) {
+            let (device, error) = open_device(info);
+            (device, error.is_some(), error)
+        } else {
+            match (last, last_profile) {
+                (Some(id), Some(p)) if id == Device::simulated_id(p) => (Device::simulated(p), false, None),
+                // wait for it to be plugged in
+                (Some(id), Some(p)) => (Device::offline(p, id), true, None),
+                _ => {
+                    log::warn!("no hardware device found, using a simulated one");
+                    auto_connect = true;
+                    (Device::simulated(DeviceProfile::default_profile()), false, None)
+                }
+            }
+        };
+        watch(&cc.egui_ctx, &device);
+
+        let (state, device_state) = load_state(cc.storage, &device);
+        let scenes = load_scenes(cc.storage, &device);
+
+        let (osc_enabled, osc_port) = cc.storage.and_then(|storage| eframe::get_value(storage, OSC_KEY))
+            .unwrap_or((false, osc::DEFAULT_PORT));
+        let (midi_enabled, midi_feedback) = cc.storage.and_then(|storage| eframe::get_value(storage, MIDI_KEY))
+            .unwrap_or((false, false));
+        let midi = Midi::new(midi_enabled, midi_feedback, load_mappings(cc.storage, &device));
+        let dbus_enabled = cc.storage.and_then(|storage| eframe::get_value(storage, DBUS_KEY)).unwrap_or(false);
+        let show_meters = cc.storage.and_then(|storage| eframe::get_value(storage, METERS_KEY)).unwrap_or(false);
 
-        ScarlettControlApp {
+        let ctx = cc.egui_ctx.clone();
+        let mut app = ScarlettControlApp {
             device,
-            state
+            state,
+            device_state,
+            devices,
+            offline,
+            error: None,
+            cards: CardWatcher::new(Box::new(move || ctx.request_repaint())),
+            auto_connect,
+            scenes,
+            show_scenes: false,
+            mixer_view: MixerView::Cards,
+            show_meters,
+            meters: None,
+            file_dialog: None,
+            history: History::default(),
+            osc_enabled,
+            osc_port,
+            osc: None,
+            osc_error: None,
+            midi,
+            dbus_enabled,
+            dbus: None,
+            dbus_error: None
+        };
+        if let Some(e) = open_error.or(list_error) {
+            app.report(e);
+        }
+        app
+    }
+
+    // show `e` to the user, and carry on without the device if it can't be used any more
+    fn report(&mut self, e: DeviceError) {
+        if self.error.as_ref() != Some(&e) {
+            log::error!("{}", e);
+        }
+        if e.is_fatal() && !self.offline {
+            self.device = Device::offline(self.device.profile, self.device.id.clone());
+            self.offline = true;
+        }
+        self.error = Some(e);
+    }
+
+    fn check(&mut self, result: Result<(), DeviceError>) {
+        if let Err(e) = result {
+            self.report(e);
+        }
+    }
+
+    // start or stop reading levels to match the device and whether they're shown
+    fn run_meters(&mut self, ctx: &egui::Context) {
+        if !self.show_meters || self.offline {
+            self.meters = None;
+            return;
+        }
+        if !self.meters.as_ref().is_some_and(|m| m.is_for(&self.device)) {
+            let ctx = ctx.clone();
+            self.meters = Some(Meters::new(&self.device, Box::new(move || ctx.request_repaint())));
+        }
+        if let Some(meters) = &mut self.meters {
+            meters.update(ctx.input(|i| i.time));
+        }
+    }
+
+    fn run_osc(&mut self, ctx: &egui::Context) {
+        if !self.osc_enabled {
+            self.osc = None;
+            self.osc_error = None;
+            return;
+        }
+        let port = self.osc_port;
+        if self.osc.as_ref().is_some_and(|o| o.port() == port) || self.osc_error.as_ref().is_some_and(|(p, _)| *p == port) {
+            return;
+        }
+        // let go of the old port first
+        self.osc = None;
+        let ctx = ctx.clone();
+        match OscServer::new(port, Box::new(move || ctx.request_repaint())) {
+            Ok(server) => {
+                log::info!("osc server listening on port {}", port);
+                self.osc = Some(server);
+                self.osc_error = None;
+            }
+            Err(e) => self.osc_error = Some((port, e.to_string()))
+        }
+    }
+
+    fn run_dbus(&mut self, ctx: &egui::Context) {
+        if !self.dbus_enabled {
+            self.dbus = None;
+            self.dbus_error = None;
+            return;
+        }
+        if self.dbus.is_some() || self.dbus_error.is_some() {
+            return;
+        }
+        let ctx = ctx.clone();
+        match DbusService::new(Box::new(move || ctx.request_repaint())) {
+            Ok(service) => {
+                log::info!("d-bus service started");
+                self.dbus = Some(service);
+            }
+            Err(e) => {
+                log::warn!("couldn't start the d-bus service: {}", e);
+                self.dbus_error = Some(e.to_string());
+            }
+        }
+    }
+
+    fn switch_device(&mut self, ctx: &egui::Context, mut storage: Option<&mut (dyn eframe::Storage + 'static)>, choice: DeviceChoice) {
+        // the same device coming back, rather than a different one
+        let reconnecting = matches!(&choice, DeviceChoice::Hardware(info) if info.id == self.device.id && self.offline);
+        let (device, error) = match choice {
+            DeviceChoice::Hardware(info) if info.id != self.device.id || reconnecting => open_device(&info),
+            DeviceChoice::Simulated(p) if Device::simulated_id(p) != self.device.id => (Device::simulated(p), None),
+            _ => return
+        };
+        self.auto_connect = false;
+
+        if reconnecting && error.is_none() {
+            // carry on from where we were, which gets written to it on the next update
+            log::info!("{} reconnected", device.describe());
+            watch(ctx, &device);
+            self.device = device;
+            self.offline = false;
+            self.error = None;
+            return;
+        }
+
+        // hang on to the state of the one we're leaving
+        if let Some(storage) = storage.as_deref_mut() {
+            schema::store(storage, &state_key(&self.device), &self.state);
+            schema::store(storage, &scenes_key(&self.device), &self.scenes);
+            eframe::set_value(storage, &mappings_key(&self.device), &self.midi.mappings);
+        }
+
+        watch(ctx, &device);
+        let (state, device_state) = load_state(storage.as_deref(), &device);
+        self.scenes = load_scenes(storage.as_deref(), &device);
+        self.midi.mappings = load_mappings(storage.as_deref(), &device);
+        self.midi.learning = None;
+        self.device = device;
+        self.state = state;
+        self.history.clear();
+        self.device_state = device_state;
+        self.offline = error.is_some();
+        self.error = None;
+        if let Some(e) = error {
+            self.report(e);
+        }
+    }
+
+    // react to interfaces being plugged in or unplugged
+    fn hotplug(&mut self) -> Option<DeviceChoice> {
+        let (devices, error) = list_devices();
+        self.devices = devices;
+        if let Some(e) = error {
+            self.report(e);
+        }
+        let current = self.devices.iter().find(|d| d.id == self.device.id).cloned();
+        if self.offline {
+            current.map(DeviceChoice::Hardware)
+        } else if self.device.is_hardware() {
+            // usually noticed by an alsa call failing first, but not always
+            if current.is_none() {
+                self.report(DeviceError::CardNotFound(self.device.describe()));
+            }
+            None
+        } else if self.auto_connect {
+            self.devices.first().cloned().map(DeviceChoice::Hardware)
+        } else {
+            None
+        }
+    }
+
+    // the device we were using, if it's plugged in again
+    fn find_again(&mut self) -> Option<DeviceChoice> {
+        let (devices, error) = list_devices();
+        self.devices = devices;
+        let found = self.devices.iter().find(|d| d.id == self.device.id).cloned();
+        match (found, error) {
+            (Some(info), _) => Some(DeviceChoice::Hardware(info)),
+            (None, Some(e)) => {
+                self.report(e);
+                None
+            }
+            (None, None) => {
+                self.report(DeviceError::CardNotFound(self.device.profile.name.to_owned()));
+                None
+            }
         }
     }
+
+    // non-modal, so the rest of the app can still be used
+    fn error_banner(&mut self, ctx: &egui::Context) -> Option<DeviceChoice> {
+        let mut choice = None;
+        egui::TopBottomPanel::top("error_banner")
+            .frame(Frame {
+                inner_margin: Margin::symmetric(8.0, 4.0),
+                fill: theme::colors::FAINT,
+                ..Default::default()
+            })
+            .show(ctx, |ui| {
+                let color = ui.visuals().error_fg_color;
+                Flex::horizontal().w_full().align_items(FlexAlign::Center).gap(vec2(8.0, 8.0)).show(ui, |flex| {
+                    if let Some(e) = &self.error {
+                        flex.add_ui(item(), |ui| {
+                            ui.label(egui_material_icons::icon_text(ICON_ERROR).color(color));
+                        });
+                        flex.add_ui(item().grow(1.0), |ui| {
+                            ui.label(e.to_string());
+                        });
+                    } else {
+                        flex.add_ui(item(), |ui| {
+                            ui.label(egui_material_icons::icon_text(ICON_WARNING).color(color));
+                        });
+                        flex.add_ui(item().grow(1.0), |ui| {
+                            ui.label(format!("{} isn't connected", self.device.profile.name));
+                        });
+                    }
+                    if self.offline {
+                        flex.add_ui(item(), |ui| {
+                            ui.label(RichText::new("Changes will be sent when it's plugged back in").weak());
+                        });
+                        flex.add_ui(item(), |ui| {
+                            if ui.button("Reconnect").clicked() {
+                                choice = self.find_again();
+                            }
+                        });
+                    }
+                    if self.error.is_some() {
+                        flex.add_ui(item(), |ui| {
+                            if icon_button(ui, ICON_CLOSE).clicked() {
+                                self.error = None;
+                            }
+                        });
+                    }
+                });
+            });
+        choice
+    }
+
+    // waiting for a control to be moved, after "MIDI learn"
+    fn learn_banner(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::top("learn_banner")
+            .frame(Frame {
+                inner_margin: Margin::symmetric(8.0, 4.0),
+                fill: theme::colors::FAINT,
+                ..Default::default()
+            })
+            .show(ctx, |ui| {
+                Flex::horizontal().w_full().align_items(FlexAlign::Center).gap(vec2(8.0, 8.0)).show(ui, |flex| {
+                    flex.add_ui(item().grow(1.0), |ui| {
+                        ui.label("MIDI learn: move a fader or press a button on the controller");
+                    });
+                    flex.add_ui(item(), |ui| {
+                        if ui.button("Cancel").clicked() {
+                            self.midi.learning = None;
+                        }
+                    });
+                });
+            });
+    }
+
+    fn device_menu(&mut self, ui: &mut egui::Ui) -> Option<DeviceChoice> {
+        let mut choice = None;
+        ui.menu_button(self.device.describe(), |ui| {
+            for info in &self.devices {
+                let label = format!("{} (card {})", info.profile.name, info.card);
+                let selected = info.id == self.device.id && !self.offline;
+                if ui.selectable_label(selected, label).on_hover_text(&info.longname).clicked() {
+                    choice = Some(DeviceChoice::Hardware(info.clone()));
+                    ui.close_menu();
+                }
+            }
+            if self.devices.is_empty() {
+                ui.label(RichText::new("No interfaces found").weak());
+            }
+            ui.menu_button("Simulated", |ui| {
+                for p in PROFILES {
+                    if ui.selectable_label(Device::simulated_id(p) == self.device.id, p.name).clicked() {
+                        choice = Some(DeviceChoice::Simulated(p));
+                        ui.close_menu();
+                    }
+                }
+            });
+            ui.separator();
+            if ui.button("Refresh").clicked() {
+                let (devices, error) = list_devices();
+                self.devices = devices;
+                if let Some(e) = error {
+                    self.report(e);
+                }
+            }
+        });
+        choice
+    }
 }
 
 fn set_menu_style(style: &mut Style) {
@@ -88,37 +605,85 @@ impl ScarlettControlApp {
         });
         ui.add_space(4.0);
         egui::Grid::new("capture_g")
-            .num_columns(2)
+            .num_columns(3)
             .striped(true)
             .show(ui, |ui| {
                 for (i, selected) in self.state.capture.as_mut_slice().iter_mut().enumerate() {
                     let label = (i + 1).to_string();
                     ui.label(label.clone());
+                    let text = match selected {
+                        Some(port) => ports_text(ui, &[port], &self.device.audio_sources),
+                        None => RichText::new("Off")
+                    };
                     egui::ComboBox::from_id_salt(label)
-                        .selected_text(selected.map_or("Off".to_owned(), |s| s.to_string()))
+                        .selected_text(text)
                         .show_ui(ui, |ui| {
                             ui.selectable_value(selected, None, "Off");
-                            for (i, text) in self.device.audio_sources.iter().enumerate()/*AudioSource::VARIANTS*/ {
-                                ui.selectable_value( selected, Some(i), text);
+                            for text in &self.device.audio_sources/*AudioSource::VARIANTS*/ {
+                                ui.selectable_value( selected, Some(Port::new(text)), text);
                             }
                         });    
+                    match self.meters.as_mut().and_then(|m| m.channel(i)) {
+                        Some(m) if selected.is_some() => {
+                            m.ui(ui);
+                        }
+                        _ => {
+                            ui.label("");
+                        }
+                    }
                     ui.end_row();    
                 }
             });
+        if let Some(e) = self.meters.as_ref().and_then(Meters::error) {
+            ui.add_space(4.0);
+            ui.label(RichText::new(format!("Meters unavailable: {}", e)).weak());
+        }
     }
 
     fn mixer_controls(&mut self, ui: &mut egui::Ui) {
+        Flex::horizontal().w_full().align_items_content(Align2::LEFT_TOP).show(ui, |flex| {
+            flex.add_ui(item().grow(1.0), |ui| ui.heading("Mixer"));
+            flex.add_ui(item(), |ui| {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_salt("pan_law")
+                        .selected_text(format!("Pan law {}", self.state.pan_law))
+                        .show_ui(ui, |ui| {
+                            for law in PanLaw::ALL {
+                                ui.selectable_value(&mut self.state.pan_law, law, law.to_string());
+                            }
+                        })
+                        .response
+                        .on_hover_text("How much quieter a mono source panned to the middle of a stereo destination is on each side");
+                    for (view, icon, hint) in [
+                        (MixerView::Cards, ICON_VIEW_AGENDA, "Show entries as cards"),
+                        (MixerView::Strips, ICON_TUNE, "Show destinations as faders"),
+                        (MixerView::Matrix, ICON_GRID_ON, "Show the routing matrix")
+                    ] {
+                        if ui.selectable_label(self.mixer_view == view, icon).on_hover_text(hint).clicked() {
+                            self.mixer_view = view;
+                        }
+                    }
+                });
+            });
+        });
+        ui.add_space(8.0);
+        match self.mixer_view {
+            MixerView::Matrix => return matrix::show(ui, &mut self.state, &self.device),
+            MixerView::Strips => return self.mixer_strips(ui),
+            MixerView::Cards => {}
+        }
+
         Flex::vertical()
             .w_full()
             .align_items_content(Align2::LEFT_TOP)
             .gap(vec2(8.0, 8.0))
             .show(ui, |flex| {
-                flex.add_ui(item(), |ui| ui.heading("Mixer"));
-
-                let remaining_channels = 18 - self.state.mixer_entries.iter()
+                let remaining_channels = self.device.profile.matrix_inputs as i32 - self.state.mixer_entries.iter()
                     .filter(|e| e.enabled)
                     .fold(0, |a, e| a + (if e.stereo {2} else {1}));
 
+                let capture = self.state.capture.clone();
+                let heard = self.heard();
                 let mut to_remove: Vec<usize> = Vec::new();
                 for (i, m) in self.state.mixer_entries.iter_mut().enumerate() {
                     flex.add_ui(item(), |ui| {
@@ -140,6 +705,13 @@ impl ScarlettControlApp {
                                         }
                                     )
                                 });
+                                flex.add_ui(item(), |ui| {
+                                    ui.horizontal(|ui| {
+                                        let mute = mute_button(ui, &mut m.mute);
+                                        midi::menu(&mute, Target::EntryMute(m.id));
+                                        solo_button(ui, &mut m.solo);
+                                    });
+                                });
                                 flex.add(
                                     item().grow(1.0),
                                     egui::TextEdit::singleline(&mut m.name)
@@ -167,15 +739,19 @@ impl ScarlettControlApp {
                                     variant_combobox(ui, format!("m-{}", i), &self.device.audio_sources,
                                         &mut m.source);
                                 }    
+                                let sources: &[&Port] = if m.stereo { &[&m.source, &m.source_r] } else { &[&m.source] };
+                                port_meters(ui, &mut self.meters, &capture, sources);
                             });
                             ui.add_space(4.0);
                             ui.label("Destinations");
                             if !m.dests.is_empty() {
                                 let mut dests_to_remove = Vec::<usize>::new();
+                                let (stereo, entry) = (m.stereo, m.id);
+                                let heard = &heard[i];
                                 egui::Grid::new(format!("dg-{}", i)).num_columns(1).start_row(1)
                                     .striped(true).show(ui, |ui| {
                                         for (j, d) in &mut m.dests.iter_mut().enumerate() {
-                                            destination(ui, format!("{}-{}", i, j), d, &self.device, || {
+                                            destination(ui, entry, d, stereo, heard[j], &self.device, || {
                                                 dests_to_remove.push(j);
                                             });
                                             ui.end_row();
@@ -212,12 +788,213 @@ impl ScarlettControlApp {
     }
 }
 
-fn destination<F>(ui: &mut egui::Ui, id_salt: String, d: &mut MixerDestination, device: &Device, delete: F) where F: FnOnce() -> () {
+impl ScarlettControlApp {
+    // for each destination of each entry, whether it can be heard in any of its mixes
+    fn heard(&self) -> Vec<Vec<bool>> {
+        let soloed = soloed_mixes(&self.state, self.device.profile.matrix_inputs);
+        self.state.mixer_entries.iter()
+            .map(|e| e.dests.iter()
+                .map(|d| routes(e.stereo, d).into_iter().any(|(_, mix)| audible(e, d, mix, &soloed)))
+                .collect())
+            .collect()
+    }
+
+    fn mixer_strips(&mut self, ui: &mut egui::Ui) {
+        let capture = self.state.capture.clone();
+        let heard = self.heard();
+        let mixes = &self.device.mixer_destinations;
+        egui::ScrollArea::horizontal().show(ui, |ui| {
+            ui.horizontal_top(|ui| {
+                for (i, m) in self.state.mixer_entries.iter_mut().enumerate().filter(|(_, m)| m.enabled) {
+                    card_frame(true).show(ui, |ui| {
+                        ui.vertical(|ui| {
+                            ui.horizontal(|ui| {
+                                ui.label(RichText::new(&m.name).strong());
+                                let mute = mute_button(ui, &mut m.mute);
+                                midi::menu(&mute, Target::EntryMute(m.id));
+                                solo_button(ui, &mut m.solo);
+                            });
+                            let sources: &[&Port] = if m.stereo { &[&m.source, &m.source_r] } else { &[&m.source] };
+                            port_meters(ui, &mut self.meters, &capture, sources);
+                            ui.add_space(4.0);
+                            if m.dests.is_empty() {
+                                ui.label(RichText::new("No destinations").weak());
+                            }
+                            ui.horizontal_top(|ui| {
+                                for (j, d) in m.dests.iter_mut().enumerate() {
+                                    ui.push_id((i, j), |ui| {
+                                        ui.with_layout(egui::Layout::top_down(Align::Center), |ui| {
+                                            ui.set_width(80.0);
+                                            let dests: &[&Port] = if d.stereo { &[&d.dest, &d.dest_r] } else { &[&d.dest] };
+                                            ui.label(ports_text(ui, dests, mixes));
+                                            if d.stereo {
+                                                pan_slider(ui, &mut d.pan, m.stereo);
+                                            }
+                                            let fader = ui.add(Fader::new(&mut d.gain).muted(!heard[i][j]));
+                                            midi::menu(&fader, Target::DestGain(m.id, d.id));
+                                            ui.add(gain_drag_value(&mut d.gain));
+                                            ui.horizontal(|ui| {
+                                                let mute = mute_button(ui, &mut d.mute);
+                                                midi::menu(&mute, Target::DestMute(m.id, d.id));
+                                                solo_button(ui, &mut d.solo);
+                                            });
+                                        });
+                                    });
+                                }
+                            });
+                        });
+                    });
+                }
+            });
+        });
+    }
+
+    fn scenes_panel(&mut self, ui: &mut egui::Ui) {
+        Flex::horizontal().w_full().align_items_content(Align2::LEFT_TOP).show(ui, |flex| {
+            flex.add_ui(item().grow(1.0), |ui| ui.heading("Scenes"));
+            flex.add_ui(item(), |ui| {
+                if icon_button(ui, ICON_ADD).on_hover_text("Save the current settings as a new scene").clicked() {
+                    self.scenes.push(Scene {
+                        name: Scene::unique_name(&self.scenes, "Scene"),
+                        state: self.state.clone()
+                    });
+                }
+            });
+        });
+        ui.add_space(4.0);
+        if self.scenes.is_empty() {
+            ui.label(RichText::new("No scenes yet").weak());
+            return;
+        }
+
+        let mut action = None;
+        egui::Grid::new("scenes_g")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                for (i, scene) in self.scenes.iter_mut().enumerate() {
+                    ui.add(egui::TextEdit::singleline(&mut scene.name)
+                        .background_color(egui::Color32::TRANSPARENT)
+                        .desired_width(120.0));
+                    ui.horizontal(|ui| {
+                        for (icon, hint, a) in [
+                            (ICON_PLAY_ARROW, "Recall", SceneAction::Recall(i)),
+                            (ICON_SAVE, "Overwrite with the current settings", SceneAction::Store(i)),
+                            (ICON_CONTENT_COPY, "Duplicate", SceneAction::Duplicate(i)),
+                            (ICON_DELETE, "Delete", SceneAction::Delete(i))
+                        ] {
+                            if icon_button(ui, icon).on_hover_text(hint).clicked() {
+                                action = Some(a);
+                            }
+                        }
+                    });
+                    ui.end_row();
+                }
+            });
+
+        match action {
+            Some(SceneAction::Recall(i)) => {
+                let mut state = self.scenes[i].state.clone();
+                state.fit(&self.device);
+                // gets to the hardware through the usual update at the end of the frame
+                self.state = state;
+            }
+            Some(SceneAction::Store(i)) => self.scenes[i].state = self.state.clone(),
+            Some(SceneAction::Duplicate(i)) => {
+                let scene = Scene {
+                    name: Scene::unique_name(&self.scenes, &format!("{} copy", self.scenes[i].name)),
+                    state: self.scenes[i].state.clone()
+                };
+                self.scenes.insert(i + 1, scene);
+            }
+            Some(SceneAction::Delete(i)) => {
+                self.scenes.remove(i);
+            }
+            None => {}
+        }
+    }
+
+    fn file_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &mut self.file_dialog else {
+            return;
+        };
+        let mut open = true;
+        let mut done = false;
+        egui::Window::new(if dialog.import { "Import" } else { "Export" })
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(RichText::new("Files ending in .json are json, anything else is toml").weak());
+                ui.add(egui::TextEdit::singleline(&mut dialog.path).desired_width(300.0));
+                if let Some(message) = &dialog.message {
+                    ui.label(message);
+                }
+                ui.add_space(4.0);
+                if ui.button(if dialog.import { "Import" } else { "Export" }).clicked() {
+                    let path = std::path::Path::new(&dialog.path);
+                    if dialog.import {
+                        match SessionFile::load(path) {
+                            Ok(file) => {
+                                let (state, missing) = file.to_state(&self.device);
+                                self.state = state;
+                                if missing.is_empty() {
+                                    done = true;
+                                } else {
+                                    dialog.message = Some(format!("Imported, but this interface has nothing called {}", missing.join(", ")));
+                                }
+                            }
+                            Err(e) => dialog.message = Some(e)
+                        }
+                    } else {
+                        match SessionFile::from_state(&self.state, &self.device).save(path) {
+                            Ok(()) => done = true,
+                            Err(e) => dialog.message = Some(e)
+                        }
+                    }
+                }
+            });
+        if done || !open {
+            self.file_dialog = None;
+        }
+    }
+
+    fn device_state_prompt(&mut self, ctx: &egui::Context) {
+        egui::Modal::new(egui::Id::new("device_state_prompt")).show(ctx, |ui| {
+            ui.set_width(300.0);
+            ui.heading("Device out of sync");
+            ui.label("The interface's current settings don't match the saved session. \
+                They may have been changed by another program or from the front panel.");
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                if ui.button("Load from device").clicked() {
+                    self.state = self.device_state.take().unwrap();
+                }
+                if ui.button("Load saved session").clicked() {
+                    self.device_state = None;
+                }
+            });
+        });
+    }
+}
+
+// `heard` is false when it's muted or something else is soloed, and `entry` is the id of the entry it's in
+fn destination<F>(ui: &mut egui::Ui, entry: Id, d: &mut MixerDestination, source_stereo: bool, heard: bool, device: &Device, delete: F) where F: FnOnce() {
+    let id_salt = format!("{}-{}", entry, d.id);
     Flex::horizontal().w_full().align_items(FlexAlign::Center).align_items_content(Align2::LEFT_CENTER)
         .gap(vec2(12.0, 12.0)).show(ui, |flex| {
             flex.add_ui(item(), |ui| {
-                ui.add(gain_drag_value(&mut d.gain));
+                if !heard {
+                    ui.style_mut().visuals.override_text_color = Some(theme::colors::TEXT_DISABLED);
+                }
+                mute_gain(ui, &mut d.mute, &mut d.gain, Some((Target::DestMute(entry, d.id), Target::DestGain(entry, d.id))));
+                solo_button(ui, &mut d.solo);
             });
+            if d.stereo {
+                flex.add_ui(item(), |ui| {
+                    pan_slider(ui, &mut d.pan, source_stereo);
+                });
+            }
             flex.add_ui(item().grow(1.0), |ui| {
                 if d.stereo {
                     mono_stereo_combobox(ui, format!("mc-{}", id_salt), &device.mixer_destinations, &mut d.dest, &mut d.dest_r, &mut d.split);
@@ -246,34 +1023,145 @@ fn add_button<'a>(ui: &mut egui::Ui) -> egui::Button<'a> {
 impl eframe::App for ScarlettControlApp {
     // save state before shutdown
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
-        eframe::set_value(storage, eframe::APP_KEY, &self.state);
+        schema::store(storage, &state_key(&self.device), &self.state);
+        schema::store(storage, &scenes_key(&self.device), &self.scenes);
+        eframe::set_value(storage, DEVICE_KEY, &self.device.id);
+        eframe::set_value(storage, PROFILE_KEY, &self.device.profile.name);
+        eframe::set_value(storage, OSC_KEY, &(self.osc_enabled, self.osc_port));
+        eframe::set_value(storage, &mappings_key(&self.device), &self.midi.mappings);
+        eframe::set_value(storage, MIDI_KEY, &(self.midi.enabled, self.midi.feedback));
+        eframe::set_value(storage, DBUS_KEY, &self.dbus_enabled);
+        eframe::set_value(storage, METERS_KEY, &self.show_meters);
     }
 
     // repaint
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        let polled = self.device.poll_changes();
+        self.check(polled);
+        // hold off on external changes while the user is dragging something, so it doesn't jump around
+        if self.device_state.is_none() && !ctx.is_using_pointer() {
+            self.device.sync(&mut self.state);
+        }
+
+        let mut choice = if self.cards.changed() {
+            self.hotplug()
+        } else {
+            None
+        };
+        self.run_meters(ctx);
+        self.run_osc(ctx);
+        // like changes from the hardware, these aren't steps to undo
+        if let Some(osc) = &mut self.osc {
+            osc.receive(&mut self.state, &self.device);
+        }
+        self.midi.run(ctx);
+        self.midi.receive(&mut self.state);
+        self.run_dbus(ctx);
+        if let Some(dbus) = &mut self.dbus {
+            dbus.receive(&mut self.state, &self.scenes, &self.device);
+        }
+        // text fields have their own undo
+        let (mut undo, mut redo) = if ctx.wants_keyboard_input() {
+            (false, false)
+        } else {
+            // ctrl+z would match ctrl+shift+z too
+            ctx.input_mut(|i| {
+                let redo = i.consume_shortcut(&REDO);
+                (!redo && i.consume_shortcut(&UNDO), redo)
+            })
+        };
+        // anything the ui changes from here on is an edit that can be undone
+        let mut before = self.state.clone();
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             Flex::horizontal().w_full().justify(FlexJustify::SpaceBetween).align_items(FlexAlign::Center).show(ui, |flex| {
                 flex.add_ui(item(), |ui| {
                     set_menu_style(ui.style_mut());
-                    ui.menu_button("File", |ui| {
-                        if ui.button("Quit").clicked() {
-                            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
-                        }
-                    });    
+                    ui.horizontal(|ui| {
+                        ui.menu_button("File", |ui| {
+                            ui.checkbox(&mut self.show_scenes, "Scenes");
+                            ui.checkbox(&mut self.show_meters, "Meters")
+                                .on_hover_text("Levels of the capture channels. Gen 1 interfaces are metered by recording from them, which nothing else can do at the same time");
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut self.osc_enabled, "OSC server on port")
+                                    .on_hover_text("For controlling the mixer from eg. TouchOSC");
+                                ui.add(egui::DragValue::new(&mut self.osc_port).range(1024..=65535));
+                            });
+                            if let Some((_, e)) = &self.osc_error {
+                                ui.label(RichText::new(e).color(theme::colors::CLIP));
+                            }
+                            ui.checkbox(&mut self.midi.enabled, "MIDI")
+                                .on_hover_text("Right-click a fader, mute or Hi-Z switch to map a MIDI controller to it");
+                            ui.add_enabled(self.midi.enabled, egui::Checkbox::new(&mut self.midi.feedback, "MIDI feedback"))
+                                .on_hover_text("Send mapped values back, for motor faders and button lights");
+                            if let Some(e) = &self.midi.error {
+                                ui.label(RichText::new(format!("MIDI unavailable: {}", e)).color(theme::colors::CLIP));
+                            }
+                            ui.checkbox(&mut self.dbus_enabled, "D-Bus service")
+                                .on_hover_text("For desktop widgets and keyboard shortcuts");
+                            if let Some(e) = &self.dbus_error {
+                                ui.label(RichText::new(format!("D-Bus unavailable: {}", e)).color(theme::colors::CLIP));
+                            }
+                            ui.separator();
+                            if ui.button("Import…").clicked() {
+                                self.file_dialog = Some(FileDialog::new(true));
+                                ui.close_menu();
+                            }
+                            if ui.button("Export…").clicked() {
+                                self.file_dialog = Some(FileDialog::new(false));
+                                ui.close_menu();
+                            }
+                            ui.separator();
+                            if ui.button("Quit").clicked() {
+                                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                            }
+                        });
+                        ui.menu_button("Edit", |ui| {
+                            let undo_button = egui::Button::new("Undo").shortcut_text(ctx.format_shortcut(&UNDO));
+                            if ui.add_enabled(self.history.can_undo(), undo_button).clicked() {
+                                undo = true;
+                                ui.close_menu();
+                            }
+                            let redo_button = egui::Button::new("Redo").shortcut_text(ctx.format_shortcut(&REDO));
+                            if ui.add_enabled(self.history.can_redo(), redo_button).clicked() {
+                                redo = true;
+                                ui.close_menu();
+                            }
+                        });
+                    });
+                });
+
+                flex.add_ui(item(), |ui| {
+                    set_menu_style(ui.style_mut());
+                    choice = self.device_menu(ui).or(choice.take());
                 });
 
                 flex.add_flex(item(), Flex::horizontal().gap(vec2(8.0, 8.0)).justify(FlexJustify::Center), |flex| {
-                    flex.add_ui(item(), |ui| {
-                        ui.label(RichText::new("Global").weak());
-                    });
-                    flex.add_ui(item(), |ui| {
-                        mute_gain(ui, &mut self.state.global_mute, &mut self.state.global_gain);
-                    });
+                    // gen 2+ only have the hardware knob
+                    if self.device.profile.global().is_some() {
+                        flex.add_ui(item(), |ui| {
+                            ui.label(RichText::new("Global").weak());
+                        });
+                        flex.add_ui(item(), |ui| {
+                            mute_gain(ui, &mut self.state.global_mute, &mut self.state.global_gain, Some((Target::GlobalMute, Target::GlobalGain)));
+                        });
+                    }
                     flex.add_ui(item(), |ui| {
                         ui.horizontal(|ui| {
-                            for (i, l) in [&mut self.state.hi_z_1, &mut self.state.hi_z_2].iter_mut().enumerate() {
-                                if ui.selectable_label(**l, format!("HiZ {}", i + 1)).clicked() {
-                                    **l = !**l;
+                            let p = self.device.profile;
+                            for (label, inputs, values) in [
+                                ("HiZ", p.hi_z, &mut self.state.hi_z),
+                                ("Pad", p.pad, &mut self.state.pad),
+                                ("Air", p.air, &mut self.state.air)
+                            ] {
+                                for (k, (n, l)) in inputs.iter().zip(values.iter_mut()).enumerate() {
+                                    let response = ui.selectable_label(*l, format!("{} {}", label, n));
+                                    if response.clicked() {
+                                        *l = !*l;
+                                    }
+                                    if label == "HiZ" {
+                                        midi::menu(&response, Target::HiZ(k));
+                                    }
                                 }
                             }
                         })
@@ -282,19 +1170,35 @@ impl eframe::App for ScarlettControlApp {
             });
         });
 
+        if self.error.is_some() || self.offline {
+            choice = self.error_banner(ctx).or(choice);
+        }
+        if self.midi.learning.is_some() {
+            self.learn_banner(ctx);
+        }
+
+        if let Some(choice) = choice {
+            self.switch_device(ctx, frame.storage_mut(), choice);
+            before = self.state.clone();
+        }
+
         egui::TopBottomPanel::bottom("bottom_panel").show(ctx, |ui| {
             ui.add_space(2.0);
             // ui.heading("Outputs");
+            let capture = self.state.capture.clone();
             egui::Grid::new("bottom_g").num_columns(2).start_row(1).striped(true).show(ui, |ui| {
-                for o in self.state.outputs.as_mut() {
+                for (i, o) in self.state.outputs.iter_mut().enumerate() {
                     ui.label(o.name.clone());
                     Flex::horizontal().w_full().align_items(FlexAlign::Center).gap(vec2(12.0, 12.0)).show(ui, |flex| {
                         flex.add_ui(item(), |ui| {
-                            mute_gain(ui, &mut o.mute, &mut o.gain);
+                            mute_gain(ui, &mut o.mute, &mut o.gain, Some((Target::OutputMute(i), Target::OutputGain(i))));
                         });
                         flex.add_ui(item(), |ui| {
                             mono_stereo_combobox(ui, o.name.clone(), &self.device.audio_sources, &mut o.source.0, &mut o.source.1, &mut o.split);
-                        })
+                        });
+                        flex.add_ui(item(), |ui| {
+                            port_meters(ui, &mut self.meters, &capture, &[&o.source.0, &o.source.1]);
+                        });
                     });
                     ui.end_row();
                 }
@@ -314,6 +1218,21 @@ impl eframe::App for ScarlettControlApp {
             });
         });
 
+        if self.show_scenes {
+            egui::SidePanel::right("scenes")
+                .resizable(false)
+                .frame(Frame {
+                    inner_margin: Margin::symmetric(8.0, 4.0),
+                    fill: ctx.style().visuals.panel_fill,
+                    ..Default::default()
+                })
+                .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    self.scenes_panel(ui);
+                });
+            });
+        }
+
         egui::CentralPanel::default()
             .frame(Frame {
                 inner_margin: Margin::symmetric(8.0, 4.0),
@@ -325,25 +1244,105 @@ impl eframe::App for ScarlettControlApp {
                 self.mixer_controls(ui);
             });
         });
+
+        self.file_dialog(ctx);
+        self.midi.requests(ctx);
+
+        if self.device_state.is_some() {
+            self.device_state_prompt(ctx);
+        }
+
+        // a drag or some typing is one step, however many frames it takes
+        let busy = ctx.is_using_pointer() || ctx.wants_keyboard_input();
+        self.history.record(before, &self.state, busy);
+        if undo {
+            self.history.undo(&mut self.state);
+        } else if redo {
+            self.history.redo(&mut self.state);
+        }
+
+        if self.device_state.is_none() {
+            let updated = self.device.update(&self.state);
+            self.check(updated);
+        }
+        // whatever changed this frame, from here or anywhere else
+        if let Some(osc) = &mut self.osc {
+            osc.send(&self.state, &self.device);
+        }
+        self.midi.send(&self.state);
+        if let Some(dbus) = &mut self.dbus {
+            dbus.send(&self.state, &self.scenes, &self.device);
+        }
     }
 }
 
-fn gain_drag_value(v: &mut f32) -> egui::DragValue {
+pub fn gain_drag_value(v: &mut f32) -> egui::DragValue<'_> {
     let gain = *v;
     egui::DragValue::new(v).speed(0.1).range(-128.0..=6.0).prefix(if gain < 0.0 { "" } else { "+" }).suffix("dB")
 }
 
-fn mute_gain(ui: &mut egui::Ui, mute: &mut bool, gain: &mut f32) {
+// with midi learn for each, when there's somewhere to map them to
+fn mute_gain(ui: &mut egui::Ui, mute: &mut bool, gain: &mut f32, targets: Option<(Target, Target)>) {
     ui.horizontal(|ui| {
-        ui.scope(|ui| {
-            if *mute {
-                ui.style_mut().visuals.override_text_color = Some(egui::Color32::BROWN /* actually red??? */);
-            }
-            if egui_material_icons::icon_button(ui, if *mute { ICON_VOLUME_OFF } else { ICON_VOLUME_UP }).clicked() {
-                *mute = !*mute;
-            }
-        });
-        ui.add(gain_drag_value(gain));
+        let mute = mute_button(ui, mute);
+        let gain = ui.add(gain_drag_value(gain));
+        if let Some((mute_target, gain_target)) = targets {
+            midi::menu(&mute, mute_target);
+            midi::menu(&gain, gain_target);
+        }
+    });
+}
+
+fn mute_button(ui: &mut egui::Ui, mute: &mut bool) -> egui::Response {
+    ui.scope(|ui| {
+        if *mute {
+            ui.style_mut().visuals.override_text_color = Some(egui::Color32::BROWN /* actually red??? */);
+        }
+        let response = egui_material_icons::icon_button(ui, if *mute { ICON_VOLUME_OFF } else { ICON_VOLUME_UP }).on_hover_text("Mute");
+        if response.clicked() {
+            *mute = !*mute;
+        }
+        response
+    }).inner
+}
+
+fn solo_button(ui: &mut egui::Ui, solo: &mut bool) {
+    if ui.selectable_label(*solo, ICON_HEADPHONES).on_hover_text("Solo, silencing everything else in the same mixes").clicked() {
+        *solo = !*solo;
+    }
+}
+
+// where a mono source is panned, or the balance of a stereo one. double-click to centre it
+fn pan_slider(ui: &mut egui::Ui, pan: &mut f32, balance: bool) {
+    let text = match (*pan * 100.0).round() as i32 {
+        0 => "C".to_owned(),
+        p if p < 0 => format!("L{}", -p),
+        p => format!("R{}", p)
+    };
+    ui.horizontal(|ui| {
+        ui.spacing_mut().slider_width = 40.0;
+        let slider = ui.add(egui::Slider::new(pan, -1.0..=1.0).show_value(false))
+            .on_hover_text(format!("{} {}, double-click to centre", if balance { "Balance" } else { "Pan" }, text));
+        if slider.double_clicked() {
+            *pan = 0.0;
+        }
+        ui.label(RichText::new(text).weak());
+    });
+}
+
+// meters for the capture channels carrying `ports`, one above the other
+fn port_meters(ui: &mut egui::Ui, meters: &mut Option<Meters>, capture: &[Option<Port>], ports: &[&Port]) {
+    let Some(meters) = meters else {
+        return;
+    };
+    ui.vertical(|ui| {
+        ui.spacing_mut().item_spacing.y = 2.0;
+        for port in ports {
+            match capture.iter().position(|c| c.as_ref() == Some(*port)).and_then(|i| meters.channel(i)) {
+                Some(m) => m.ui(ui),
+                None => meter::unavailable(ui, &format!("Route {} to a capture channel to meter it", port.0))
+            };
+        }
     });
 }
 
@@ -363,18 +1362,28 @@ fn card_frame(enabled: bool) -> egui::Frame {
         .rounding(4.0)
 }
 
+// names of `ports` for a combobox, flagged if any of them aren't in `labels`
+fn ports_text(ui: &egui::Ui, ports: &[&Port], labels: &[String]) -> RichText {
+    let text = ports.iter().map(|p| p.0.as_str()).collect::<Vec<_>>().join(" / ");
+    if ports.iter().all(|p| p.resolve(labels).is_some()) {
+        RichText::new(text)
+    } else {
+        RichText::new(format!("{} {}", ICON_WARNING, text)).color(ui.visuals().error_fg_color)
+    }
+}
+
 fn variant_combobox(
     ui: &mut egui::Ui,
     id_salt: impl std::hash::Hash,
-    labels: &Vec<String>,
-    selected: &mut EnumIndex,
+    labels: &[String],
+    selected: &mut Port,
 ) -> InnerResponse<std::option::Option<()>> {
     egui::ComboBox::from_id_salt(id_salt)
         .width(32.0)
-        .selected_text(labels[*selected].clone())
+        .selected_text(ports_text(ui, &[selected], labels))
         .show_ui(ui, |ui| {
-            for (i, text) in labels.iter().enumerate() {
-                ui.selectable_value( selected, i, text);
+            for text in labels {
+                ui.selectable_value( selected, Port::new(text), text);
             }
         })
 }
@@ -382,9 +1391,9 @@ fn variant_combobox(
 fn mono_stereo_combobox(
     ui: &mut egui::Ui,
     id_salt: String,
-    labels: &Vec<String>,
-    left: &mut EnumIndex,
-    right: &mut EnumIndex,
+    labels: &[String],
+    left: &mut Port,
+    right: &mut Port,
     split: &mut bool
 ) -> egui::InnerResponse<()> {
     Flex::horizontal()
@@ -399,18 +1408,22 @@ fn mono_stereo_combobox(
                 flex.add_ui(item().grow(1.0), |ui| variant_combobox(ui, id_salt.clone() + "_l", labels, left));
                 flex.add_ui(item().grow(1.0), |ui| variant_combobox(ui, id_salt + "_r", labels, right));
             } else {
-                if *left == labels.len() - 1 {
-                    // make sure that we can still assign the next value to the right channel
-                    *left = labels.len() - 2;
+                // a port the device doesn't have is left alone, so it's shown as missing rather than replaced
+                if let Some(mut l) = left.resolve(labels) {
+                    if l == labels.len() - 1 {
+                        // make sure that we can still assign the next value to the right channel
+                        l = labels.len().saturating_sub(2);
+                    }
+                    *left = Port::at(labels, l);
+                    *right = Port::at(labels, l + 1);
                 }
-                *right = *left + 1;
                 flex.add_ui(item().grow(1.0), |ui| {
                     egui::ComboBox::from_id_salt(id_salt)
-                        .selected_text(format!("{} / {}", left, right))
+                        .selected_text(ports_text(ui, &[left, right], labels))
                         .show_ui(ui, |ui| {
-                            for p in labels.iter().enumerate().collect::<Vec<_>>().chunks_exact(2) {
-                                if let &[l, r] = p {
-                                    ui.selectable_value(left, l.0, format!("{} / {}", l.1, r.1));
+                            for p in labels.chunks_exact(2) {
+                                if let [l, r] = p {
+                                    ui.selectable_value(left, Port::new(l), format!("{} / {}", l, r));
                                 }
                             }
                         })
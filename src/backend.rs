@@ -0,0 +1,356 @@
+use std::{collections::BTreeMap, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}};
+
+use alsa::mixer::{MilliBel, Selem, SelemChannelId, SelemId};
+
+use crate::{device::{DeviceError, ElemValue, EnumIndex}, profile::{DeviceProfile, Generation}};
+
+const CHANNEL: SelemChannelId = SelemChannelId::FrontLeft /*SelemChannelId::mono()*/;
+
+// something that exposes the interface's mixer controls, addressed by their alsa simple mixer names
+pub trait MixerBackend: Send {
+    // names of every control on the interface
+    fn controls(&self) -> Vec<String>;
+    fn get_value(&self, control: &str) -> Result<ElemValue, DeviceError>;
+    fn set_value(&mut self, control: &str, value: &ElemValue) -> Result<(), DeviceError>;
+    // labels of an enumerated control, in index order
+    fn enum_items(&self, control: &str) -> Result<Vec<String>, DeviceError>;
+    // human readable description of where the controls come from, for the ui
+    fn describe(&self) -> String;
+    // a second handle onto the same controls, so writing through it doesn't hold this one up
+    fn writer(&self) -> Result<Box<dyn MixerBackend>, DeviceError>;
+    // process change notifications without blocking, returning whether there were any
+    fn handle_events(&mut self) -> Result<bool, DeviceError> {
+        Ok(false)
+    }
+    // call `notify` from a background thread whenever a control changes
+    fn watch(&self, _notify: Box<dyn Fn() + Send>) {}
+    // whether the controls belong to a real interface, which can be unplugged
+    fn is_hardware(&self) -> bool {
+        false
+    }
+    // the pcm the interface's capture channels can be read from, if there's real audio
+    fn pcm(&self) -> Option<String> {
+        None
+    }
+}
+
+impl<'a> TryFrom<&Selem<'a>> for ElemValue {
+    type Error = alsa::Error;
+
+    fn try_from(value: &Selem) -> alsa::Result<Self> {
+        Ok(if value.is_enumerated() {
+            ElemValue::Enum(value.get_enum_item(CHANNEL)? as EnumIndex)
+        } else if !value.has_playback_volume() {
+            // plain switches, like pad and air on gen 2+
+            ElemValue::Switch(if value.has_playback_switch() {
+                value.get_playback_switch(CHANNEL)? != 0
+            } else {
+                value.get_capture_switch(CHANNEL)? != 0
+            })
+        } else {
+            ElemValue::Knob {
+                db: value.get_playback_vol_db(CHANNEL)?.to_db(),
+                // volume-only controls (the matrix gains) can't be muted
+                muted: value.has_playback_switch() && value.get_playback_switch(CHANNEL)? == 0
+            }
+        })
+    }
+}
+
+trait ElemSettable {
+    fn set_value(&self, val: &ElemValue) -> alsa::Result<()>;
+}
+
+impl ElemSettable for Selem<'_> {
+    fn set_value(&self, val: &ElemValue) -> alsa::Result<()> {
+        match val {
+            ElemValue::Enum(val) => self.set_enum_item(CHANNEL, *val as u32),
+            ElemValue::Knob { db, muted } => {
+                // set every channel so stereo outputs stay balanced
+                self.set_playback_db_all(MilliBel::from_db(*db), alsa::Round::Floor)?;
+                if self.has_playback_switch() {
+                    self.set_playback_switch_all(if *muted { 0 } else { 1 })?;
+                }
+                Ok(())
+            },
+            ElemValue::Switch(on) => if self.has_playback_switch() {
+                self.set_playback_switch_all(*on as i32)
+            } else {
+                self.set_capture_switch_all(*on as i32)
+            }
+        }
+    }
+}
+
+pub struct AlsaBackend {
+    mixer: alsa::Mixer,
+    card_name: String,
+    // eg. "hw:1"
+    device_name: String,
+    // cleared when the backend goes away, to stop the watcher thread
+    watching: Arc<AtomicBool>
+}
+
+impl Drop for AlsaBackend {
+    fn drop(&mut self) {
+        self.watching.store(false, Ordering::Relaxed);
+    }
+}
+
+impl AlsaBackend {
+    pub fn open(card: &alsa::Card) -> Result<AlsaBackend, DeviceError> {
+        let device_name = format!("hw:{}", card.get_index());
+        // nonblocking so handling events never stalls the ui
+        let mixer = alsa::Mixer::new(&device_name, true).map_err(|e| DeviceError::alsa(&device_name, e))?;
+
+        Ok(AlsaBackend {
+            mixer,
+            card_name: card.get_name().map_err(|e| DeviceError::alsa(&device_name, e))?,
+            device_name,
+            watching: Arc::new(AtomicBool::new(true))
+        })
+    }
+
+    fn selem(&self, control: &str) -> Result<Selem<'_>, DeviceError> {
+        self.mixer.find_selem(&SelemId::new(control, 0))
+            .ok_or_else(|| DeviceError::ControlMissing(control.to_owned()))
+    }
+}
+
+impl MixerBackend for AlsaBackend {
+    fn controls(&self) -> Vec<String> {
+        self.mixer.iter()
+            .flat_map(Selem::new)
+            // names that aren't utf-8 can't be any of the ones we know about
+            .filter_map(|s| s.get_id().get_name().ok().map(str::to_owned))
+            .collect()
+    }
+
+    fn get_value(&self, control: &str) -> Result<ElemValue, DeviceError> {
+        ElemValue::try_from(&self.selem(control)?).map_err(|e| DeviceError::alsa(control, e))
+    }
+
+    fn set_value(&mut self, control: &str, value: &ElemValue) -> Result<(), DeviceError> {
+        let selem = self.selem(control)?;
+        if let ElemValue::Enum(i) = value {
+            // alsa would accept it and leave the control in a state nothing understands
+            let items = selem.get_enum_items().map_err(|e| DeviceError::alsa(control, e))?;
+            if *i >= items as EnumIndex {
+                return Err(DeviceError::OutOfRange { control: control.to_owned(), value: value.clone() });
+            }
+        }
+        selem.set_value(value).map_err(|e| DeviceError::alsa(control, e))
+    }
+
+    fn enum_items(&self, control: &str) -> Result<Vec<String>, DeviceError> {
+        self.selem(control)?.iter_enum()
+            .and_then(|items| items.collect())
+            .map_err(|e| DeviceError::alsa(control, e))
+    }
+
+    fn describe(&self) -> String {
+        self.card_name.clone()
+    }
+
+    fn is_hardware(&self) -> bool {
+        true
+    }
+
+    fn pcm(&self) -> Option<String> {
+        Some(self.device_name.clone())
+    }
+
+    fn writer(&self) -> Result<Box<dyn MixerBackend>, DeviceError> {
+        // blocking, it's only used off the ui thread
+        let mixer = alsa::Mixer::new(&self.device_name, false).map_err(|e| DeviceError::alsa(&self.device_name, e))?;
+        Ok(Box::new(AlsaBackend {
+            mixer,
+            card_name: self.card_name.clone(),
+            device_name: self.device_name.clone(),
+            // nothing watches through the writer's handle
+            watching: Arc::new(AtomicBool::new(false))
+        }))
+    }
+
+    fn handle_events(&mut self) -> Result<bool, DeviceError> {
+        // values are cached by alsa-lib, so this is what makes external changes visible at all
+        self.mixer.handle_events()
+            .map(|n| n > 0)
+            .map_err(|e| DeviceError::alsa(&self.device_name, e))
+    }
+
+    fn watch(&self, notify: Box<dyn Fn() + Send>) {
+        let device_name = self.device_name.clone();
+        let watching = self.watching.clone();
+        std::thread::spawn(move || {
+            // a second handle, so the ui's one can be polled without racing this thread
+            let Ok(mixer) = alsa::Mixer::new(&device_name, false) else {
+                return;
+            };
+            // wake up every so often to see if the device was switched away from
+            while watching.load(Ordering::Relaxed) && mixer.wait(Some(500)).is_ok() {
+                match mixer.handle_events() {
+                    Ok(0) => {}
+                    Ok(_) => notify(),
+                    Err(_) => break
+                }
+            }
+        });
+    }
+}
+
+struct SimulatedControl {
+    value: ElemValue,
+    // enum labels, empty for knobs
+    items: Vec<String>,
+    // dB range for knobs
+    range: (f32, f32),
+    has_switch: bool
+}
+
+// in-memory stand-in for an interface, so the ui can run without hardware plugged in
+pub struct SimulatedBackend {
+    name: String,
+    // shared with the writer's handle
+    controls: Arc<Mutex<BTreeMap<String, SimulatedControl>>>
+}
+
+impl SimulatedBackend {
+    fn add_enum(&mut self, name: String, items: &[String], selected: EnumIndex) {
+        self.controls.lock().unwrap().insert(name, SimulatedControl {
+            value: ElemValue::Enum(selected),
+            items: items.to_vec(),
+            range: (0.0, 0.0),
+            has_switch: false
+        });
+    }
+
+    fn add_knob(&mut self, name: String, range: (f32, f32), db: f32, has_switch: bool) {
+        self.controls.lock().unwrap().insert(name, SimulatedControl {
+            value: ElemValue::Knob { db, muted: false },
+            items: Vec::new(),
+            range,
+            has_switch
+        });
+    }
+
+    fn add_switch(&mut self, name: String) {
+        self.controls.lock().unwrap().insert(name, SimulatedControl {
+            value: ElemValue::Switch(false),
+            items: Vec::new(),
+            range: (0.0, 0.0),
+            has_switch: true
+        });
+    }
+
+    fn control<T>(&self, control: &str, f: impl FnOnce(&mut SimulatedControl) -> T) -> Result<T, DeviceError> {
+        self.controls.lock().unwrap().get_mut(control)
+            .map(f)
+            .ok_or_else(|| DeviceError::ControlMissing(control.to_owned()))
+    }
+
+    // mirrors the controls snd-usb-audio creates for the interface described by `profile`
+    pub fn new(profile: &DeviceProfile) -> Self {
+        SimulatedBackend::with_name(profile, format!("{} (simulated)", profile.name))
+    }
+
+    // stands in for a real interface that can't be used
+    pub fn offline(profile: &DeviceProfile) -> Self {
+        SimulatedBackend::with_name(profile, format!("{} (offline)", profile.name))
+    }
+
+    fn with_name(profile: &DeviceProfile, name: String) -> Self {
+        let mut s = SimulatedBackend {
+            name,
+            controls: Arc::new(Mutex::new(BTreeMap::new()))
+        };
+
+        let off = || std::iter::once("Off".to_owned());
+        let matrix_items: Vec<String> = off().chain(profile.source_labels(false)).collect();
+        let master_items: Vec<String> = off().chain(profile.source_labels(true)).collect();
+        let mix_item = |m: usize| master_items.iter().position(|i| *i == profile.mix_bus(m)).unwrap_or(0);
+
+        if let Some(global) = profile.global() {
+            s.add_knob(global, (-128.0, 0.0), 0.0, true);
+        }
+        for i in 0..profile.outputs.len() {
+            for gain in profile.output_gains(i) {
+                s.add_knob(gain, (-128.0, 0.0), 0.0, true);
+            }
+            // mix pairs by default, like the driver
+            s.add_enum(profile.output_source(i, false), &master_items, mix_item(i * 2));
+            s.add_enum(profile.output_source(i, true), &master_items, mix_item(i * 2 + 1));
+        }
+
+        for n in profile.hi_z {
+            s.add_enum(profile.hi_z(*n), &["Line".to_owned(), profile.names.hi_z_on.to_owned()], 0);
+        }
+        for n in profile.pad {
+            if profile.generation == Generation::Gen1 {
+                s.add_enum(profile.pad(*n), &["0dB".to_owned(), profile.names.pad_on.to_owned()], 0);
+            } else {
+                s.add_switch(profile.pad(*n));
+            }
+        }
+        for n in profile.air {
+            s.add_switch(profile.air(*n));
+        }
+
+        for i in 0..profile.capture_channels {
+            s.add_enum(profile.capture(i), &master_items, (i + 1).min(master_items.len() - 1));
+        }
+
+        for i in 0..profile.matrix_inputs {
+            s.add_enum(profile.matrix_input(i), &matrix_items, (i + 1).min(matrix_items.len() - 1));
+            for m in 0..profile.mix_buses {
+                s.add_knob(profile.matrix_gain(i, &profile.mix_bus(m)), (-128.0, 6.0), -128.0, false);
+            }
+        }
+
+        s
+    }
+}
+
+impl MixerBackend for SimulatedBackend {
+    fn controls(&self) -> Vec<String> {
+        self.controls.lock().unwrap().keys().cloned().collect()
+    }
+
+    fn get_value(&self, control: &str) -> Result<ElemValue, DeviceError> {
+        self.control(control, |c| c.value.clone())
+    }
+
+    fn set_value(&mut self, control: &str, value: &ElemValue) -> Result<(), DeviceError> {
+        self.control(control, |c| {
+            c.value = match *value {
+                ElemValue::Enum(i) if i >= c.items.len() => return Err(DeviceError::OutOfRange {
+                    control: control.to_owned(),
+                    value: value.clone()
+                }),
+                ElemValue::Enum(i) => ElemValue::Enum(i),
+                // like alsa, gains past the ends of the range are clamped
+                ElemValue::Knob { db, muted } => ElemValue::Knob {
+                    db: db.clamp(c.range.0, c.range.1),
+                    muted: muted && c.has_switch
+                },
+                ElemValue::Switch(on) => ElemValue::Switch(on)
+            };
+            Ok(())
+        })?
+    }
+
+    fn enum_items(&self, control: &str) -> Result<Vec<String>, DeviceError> {
+        self.control(control, |c| c.items.clone())
+    }
+
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+
+    fn writer(&self) -> Result<Box<dyn MixerBackend>, DeviceError> {
+        Ok(Box::new(SimulatedBackend {
+            name: self.name.clone(),
+            controls: self.controls.clone()
+        }))
+    }
+}
@@ -0,0 +1,4 @@
+fn main() -> std::process::ExitCode {
+    env_logger::init();
+    scarlett_control::cli::run(std::env::args().skip(1).collect())
+}
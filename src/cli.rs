@@ -0,0 +1,468 @@
+// scarlett-ctl, for changing the interface from scripts and ssh sessions without the window.
+// it goes through the same mapping between the app state and the controls as the app, and only
+// writes the controls a command actually changes, so it can be used while the app is open
+//
+//     scarlett-ctl route Monitor "Mix A"
+//     scarlett-ctl mix "Analog 1" "Mix A" -6
+//     scarlett-ctl --json get "Master 1 (Monitor)"
+
+use std::{path::Path, process::ExitCode};
+
+use serde_json::{json, Value};
+
+use crate::{
+    app::{self, scenes_key, AppState},
+    daemon,
+    device::{is_port_pair, routes, Device, DeviceInfo, DeviceState, ElemValue, GAIN_MIN},
+    fader::GAIN_MAX,
+    profile::DeviceProfile,
+    schema::{self, SavedStorage},
+    session::SessionFile,
+    state::{new_id, MixerDestination, MixerEntry, Port}
+};
+
+const USAGE: &str = "\
+usage: scarlett-ctl [--device <card>] [--simulate <model>] [--json] <command> [<args>]
+
+commands:
+  devices                           the supported interfaces that are plugged in
+  list-controls                     every control and its value
+  get <control>                     one control's value
+  set <control> <value>             set a control to one of its items (or its index), a gain in dB,
+                                    mute, unmute, on or off
+  route <output> <source> [<right>] what an output plays. one source is played with the one after it
+                                    if they're a pair, eg. Mix A with Mix B
+  mix <source> <mix> <dB|off>       how loud a source is in a mix
+  load-scene <scene|file>           a scene saved in the app, or an exported session
+  dump                              everything, as an exported session would be
+  daemon [<scene|file>]             keep putting back what the app last had (or a scene) whenever an
+                                    interface is plugged in, and when it starts
+  status                            what the daemon has restored
+
+--device picks an interface by card number, hw:N, name or serial number, otherwise it's the first
+one found. --json prints json rather than text";
+
+const COMMANDS: [&str; 10] = ["devices", "list-controls", "get", "set", "route", "mix", "load-scene", "dump", "daemon", "status"];
+
+struct Options {
+    device: Option<String>,
+    simulate: Option<String>,
+    json: bool
+}
+
+// runs a command line (without the program name), printing what it says
+pub fn run(args: Vec<String>) -> ExitCode {
+    let (options, command) = match parse_options(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let Some((name, args)) = command.split_first() else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    if name == "help" {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    if !COMMANDS.contains(&name.as_str()) {
+        eprintln!("\"{name}\" isn't a command\n\n{USAGE}");
+        return ExitCode::from(2);
+    }
+    let result = if name == "devices" {
+        Ok(devices(options.json))
+    } else if name == "daemon" && args.len() <= 1 {
+        daemon::run(options.device.as_deref(), args.first().map(String::as_str))
+    } else if name == "status" && args.is_empty() {
+        daemon::status(options.json)
+    } else {
+        open(&options).and_then(|mut device| {
+            let output = command_output(&mut device, name, args, options.json)?;
+            device.finish().map_err(|e| e.to_string())?;
+            Ok(output)
+        })
+    };
+    match result {
+        Ok(output) => {
+            if !output.is_empty() {
+                println!("{output}");
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("scarlett-ctl: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_options(args: Vec<String>) -> Result<(Options, Vec<String>), String> {
+    let mut options = Options { device: None, simulate: None, json: false };
+    let mut args = args.into_iter();
+    let mut rest = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => options.json = true,
+            "--device" => options.device = Some(args.next().ok_or("--device needs a card")?),
+            "--simulate" => options.simulate = Some(args.next().ok_or("--simulate needs a model")?),
+            "-h" | "--help" => return Ok((options, vec!["help".to_owned()])),
+            a if a.starts_with("--") && rest.is_empty() => return Err(format!("unknown option {a}")),
+            _ => rest.push(arg)
+        }
+    }
+    Ok((options, rest))
+}
+
+fn open(options: &Options) -> Result<Device, String> {
+    if let Some(model) = &options.simulate {
+        let profile = DeviceProfile::find(model).ok_or_else(|| format!("there's no model called \"{model}\""))?;
+        return Ok(Device::simulated(profile));
+    }
+    let devices: Vec<DeviceInfo> = Device::list().into_iter()
+        .filter_map(|d| d.map_err(|e| log::warn!("{e}")).ok())
+        .collect();
+    let info = match &options.device {
+        Some(card) => devices.iter().find(|d| d.is_called(card))
+            .ok_or_else(|| format!("there's no supported interface called \"{card}\""))?,
+        None => devices.first().ok_or("no supported interface is plugged in")?
+    };
+    Device::open(info).map_err(|e| e.to_string())
+}
+
+fn devices(json: bool) -> String {
+    let devices: Vec<DeviceInfo> = Device::list().into_iter()
+        .filter_map(|d| d.map_err(|e| eprintln!("scarlett-ctl: {e}")).ok())
+        .collect();
+    if json {
+        let devices: Vec<Value> = devices.iter()
+            .map(|d| json!({ "card": d.card, "name": d.name, "model": d.profile.name, "id": d.id }))
+            .collect();
+        return Value::Array(devices).to_string();
+    }
+    devices.iter()
+        .map(|d| format!("hw:{}\t{}\t{}", d.card, d.profile.name, d.id))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn command_output(device: &mut Device, name: &str, args: &[String], json: bool) -> Result<String, String> {
+    match (name, args) {
+        ("list-controls", []) => {
+            let controls = device.controls();
+            if json {
+                let controls: Vec<Value> = controls.iter().map(|c| {
+                    let mut control = json!({ "name": c, "value": value_json(device, c) });
+                    if let Some(items) = device.enum_items(c) {
+                        control["items"] = json!(items);
+                    }
+                    control
+                }).collect();
+                Ok(Value::Array(controls).to_string())
+            } else {
+                Ok(controls.iter().map(|c| format!("{} = {}", c, value_text(device, c))).collect::<Vec<_>>().join("\n"))
+            }
+        }
+        ("get", [control]) => {
+            if !device.known().contains_key(control) {
+                return Err(format!("there's no control called \"{control}\""));
+            }
+            Ok(if json { value_json(device, control).to_string() } else { value_text(device, control) })
+        }
+        ("set", [control, value]) => {
+            let value = parse_value(device, control, value)?;
+            device.set(control, value).map_err(|e| e.to_string())?;
+            Ok(String::new())
+        }
+        ("route", [output, source, rest @ ..]) if rest.len() <= 1 => {
+            edit(device, |state, device| route(state, device, output, source, rest.first()))?;
+            Ok(String::new())
+        }
+        ("mix", [source, mix, gain]) => {
+            edit(device, |state, device| set_mix(state, device, source, mix, gain))?;
+            Ok(String::new())
+        }
+        ("load-scene", [scene]) => {
+            let state = saved_state(device, Some(scene))?;
+            device.update(&state).map_err(|e| e.to_string())?;
+            Ok(String::new())
+        }
+        ("dump", []) => SessionFile::from_state(&device.known().to_state(device), device).to_text(json),
+        _ => Err(format!("{name} doesn't take {} arguments\n\n{USAGE}", args.len()))
+    }
+}
+
+// change the state read from the interface, writing just the controls that change
+fn edit(device: &mut Device, change: impl FnOnce(&mut AppState, &Device) -> Result<(), String>) -> Result<(), String> {
+    for (k, v) in edits(device.known(), device, change)? {
+        device.set(&k, v).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// the controls to write for `change` to the state read from `known`. entries stay on the matrix inputs
+// they're on, rather than being packed together, since inputs nobody can hear (eg. ones the app has
+// muted) aren't read back and everything after them would move down
+fn edits(known: &DeviceState, device: &Device, change: impl FnOnce(&mut AppState, &Device) -> Result<(), String>) -> Result<Vec<(String, ElemValue)>, String> {
+    let (before, placed) = known.to_state_at(device);
+    let mut after = before.clone();
+    change(&mut after, device)?;
+    let layout = known.layout_around(&after, &placed, device).ok_or("every matrix input is in use")?;
+    let (old, new) = (DeviceState::from_state_at(&before, device, &placed), DeviceState::from_state_at(&after, device, &layout));
+    let diff = old.diff(&new);
+    Ok(diff.changed.into_iter().chain(diff.added).map(|k| {
+        let v = new[&k].clone();
+        (k, v)
+    }).collect())
+}
+
+fn route(state: &mut AppState, device: &Device, output: &str, source: &str, right: Option<&String>) -> Result<(), String> {
+    let sources = &device.audio_sources;
+    let i = state.outputs.iter()
+        .position(|o| o.name.eq_ignore_ascii_case(output))
+        .or_else(|| output.parse::<usize>().ok().filter(|n| (1..=state.outputs.len()).contains(n)).map(|n| n - 1))
+        .ok_or_else(|| format!("there's no output called \"{output}\""))?;
+    let left = find_port(sources, source)?;
+    let right = match right {
+        Some(r) => find_port(sources, r)?,
+        None => {
+            let next = left.resolve(sources).map_or(left.clone(), |l| Port::at(sources, l + 1));
+            if is_port_pair(&left, &next, sources) { next } else { left.clone() }
+        }
+    };
+    let o = &mut state.outputs[i];
+    o.split = !is_port_pair(&left, &right, sources);
+    o.source = (left, right);
+    Ok(())
+}
+
+// the gain of `source` into `mix`. the first entry playing `source` is changed, or a new one made if
+// there isn't one. "off" removes whichever of the entry's destinations is sending it there
+fn set_mix(state: &mut AppState, device: &Device, source: &str, mix: &str, gain: &str) -> Result<(), String> {
+    let source = find_port(&device.audio_sources, source)?;
+    let mix = find_port(&device.mixer_destinations, mix)?;
+    let gain = match gain {
+        "off" => None,
+        g => Some(parse_gain(g)?)
+    };
+
+    let found = state.mixer_entries.iter().position(|e| e.enabled && (e.source == source || (e.stereo && e.source_r == source)));
+    let Some(i) = found else {
+        let Some(gain) = gain else {
+            return Ok(());
+        };
+        let mut entry = MixerEntry::new(device);
+        entry.name = source.0.clone();
+        entry.source = source;
+        entry.dests = vec![mono_dest(mix, gain)];
+        state.mixer_entries.push(entry);
+        return Ok(());
+    };
+
+    let e = &mut state.mixer_entries[i];
+    let c = if e.stereo && e.source_r == source { 1 } else { 0 };
+    let dest = e.dests.iter().position(|d| routes(e.stereo, d).contains(&(c, &mix)));
+    match (dest, gain) {
+        (Some(j), Some(gain)) => {
+            e.dests[j].gain = gain;
+            e.dests[j].mute = false;
+        }
+        (Some(j), None) => {
+            e.dests.remove(j);
+        }
+        (None, Some(gain)) => e.dests.push(mono_dest(mix, gain)),
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+fn mono_dest(mix: Port, gain: f32) -> MixerDestination {
+    MixerDestination {
+        stereo: false,
+        dest: mix.clone(),
+        dest_r: mix,
+        split: false,
+        gain,
+        mute: false,
+        pan: 0.0,
+        solo: false,
+        id: new_id()
+    }
+}
+
+// a scene or exported session to put on `device`, or what the app last had on it
+pub fn saved_state(device: &Device, scene: Option<&str>) -> Result<AppState, String> {
+    let mut state = match scene {
+        Some(scene) => load_scene(device, scene)?,
+        None => {
+            let storage = SavedStorage::open()?;
+            app::saved_state(&storage, device)
+                .map(|(state, _)| state)
+                .ok_or("the app hasn't saved anything for this interface")?
+        }
+    };
+    state.fit(device);
+    Ok(state)
+}
+
+fn load_scene(device: &Device, scene: &str) -> Result<AppState, String> {
+    let path = Path::new(scene);
+    if path.is_file() {
+        let (state, missing) = SessionFile::load(path)?.to_state(device);
+        if !missing.is_empty() {
+            eprintln!("scarlett-ctl: this interface doesn't have {}", missing.join(", "));
+        }
+        return Ok(state);
+    }
+    let storage = SavedStorage::open()
+        .map_err(|e| format!("there's no file called \"{scene}\", and the app's scenes can't be read ({e})"))?;
+    let (scenes, _) = schema::load_scenes(&storage, &scenes_key(device), device)
+        .ok_or("there are no scenes saved for this interface")?;
+    scenes.into_iter()
+        .find(|s| s.name == scene)
+        .map(|s| s.state)
+        .ok_or_else(|| format!("there's no scene or file called \"{scene}\""))
+}
+
+// a port by name, ignoring case
+fn find_port(labels: &[String], name: &str) -> Result<Port, String> {
+    labels.iter()
+        .find(|l| l.eq_ignore_ascii_case(name))
+        .map(|l| Port::new(l))
+        .ok_or_else(|| format!("there's nothing called \"{name}\", it can be one of {}", labels.join(", ")))
+}
+
+fn parse_gain(text: &str) -> Result<f32, String> {
+    let number = text.trim().trim_end_matches("dB").trim_end_matches("db");
+    number.parse::<f32>().ok()
+        .filter(|db| (GAIN_MIN..=GAIN_MAX).contains(db))
+        .ok_or_else(|| format!("{text} isn't a gain from {GAIN_MIN} to {GAIN_MAX}dB"))
+}
+
+// `text` as a value for `control`, which depends on what kind of control it is
+fn parse_value(device: &Device, control: &str, text: &str) -> Result<ElemValue, String> {
+    let current = device.known().get(control).ok_or_else(|| format!("there's no control called \"{control}\""))?;
+    match current {
+        ElemValue::Enum(_) => {
+            let items = device.enum_items(control).unwrap_or_default();
+            items.iter().position(|i| i.eq_ignore_ascii_case(text))
+                .or_else(|| text.parse().ok().filter(|i| *i < items.len()))
+                .map(ElemValue::Enum)
+                .ok_or_else(|| format!("\"{control}\" can be one of {}", items.join(", ")))
+        }
+        ElemValue::Knob { db, muted } => match text {
+            "mute" => Ok(ElemValue::Knob { db: *db, muted: true }),
+            "unmute" => Ok(ElemValue::Knob { db: *db, muted: false }),
+            gain => Ok(ElemValue::Knob { db: parse_gain(gain)?, muted: *muted })
+        },
+        ElemValue::Switch(_) => match text {
+            "on" | "true" | "1" => Ok(ElemValue::Switch(true)),
+            "off" | "false" | "0" => Ok(ElemValue::Switch(false)),
+            _ => Err(format!("\"{control}\" can be on or off"))
+        }
+    }
+}
+
+fn value_json(device: &Device, control: &str) -> Value {
+    match device.known().get(control) {
+        Some(ElemValue::Enum(i)) => device.enum_items(control).and_then(|items| items.get(*i)).map_or(json!(i), |l| json!(l)),
+        Some(ElemValue::Knob { db, muted }) => json!({ "db": db, "muted": muted }),
+        Some(ElemValue::Switch(on)) => json!(on),
+        None => Value::Null
+    }
+}
+
+fn value_text(device: &Device, control: &str) -> String {
+    match device.known().get(control) {
+        Some(ElemValue::Enum(i)) => device.enum_items(control).and_then(|items| items.get(*i)).cloned().unwrap_or_else(|| i.to_string()),
+        Some(ElemValue::Knob { db, muted }) => format!("{:+.1}dB{}", db, if *muted { " (muted)" } else { "" }),
+        Some(ElemValue::Switch(on)) => (if *on { "on" } else { "off" }).to_owned(),
+        None => String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device() -> Device {
+        Device::simulated(DeviceProfile::find("Scarlett 18i6").unwrap())
+    }
+
+    #[test]
+    fn mixes_reuse_entries_and_make_new_ones() {
+        let device = device();
+        let mut state = device.known().to_state(&device);
+        let entries = state.mixer_entries.len();
+        let (source, mix) = (&device.audio_sources[3], &device.mixer_destinations[2]);
+
+        set_mix(&mut state, &device, source, mix, "-6dB").unwrap();
+        assert_eq!(state.mixer_entries.len(), entries + 1);
+        set_mix(&mut state, &device, &source.to_lowercase(), mix, "-12").unwrap();
+        let e = state.mixer_entries.last().unwrap();
+        assert_eq!(e.source.0, *source);
+        assert_eq!(e.dests.len(), 1);
+        assert_eq!(e.dests[0].gain, -12.0);
+
+        set_mix(&mut state, &device, source, mix, "off").unwrap();
+        assert!(state.mixer_entries.last().unwrap().dests.is_empty());
+        assert!(set_mix(&mut state, &device, source, mix, "+20").is_err());
+    }
+
+    #[test]
+    fn mixes_leave_entries_on_their_inputs() {
+        let device = device();
+        let mut app = device.known().to_state(&device);
+        let (sources, mixes) = (&device.audio_sources, &device.mixer_destinations);
+        let p = device.profile;
+        // the app with the first of two entries muted, which leaves its input silent
+        app.mixer_entries.clear();
+        set_mix(&mut app, &device, &sources[1], &mixes[0], "0").unwrap();
+        set_mix(&mut app, &device, &sources[2], &mixes[0], "-3").unwrap();
+        app.mixer_entries[0].mute = true;
+        let known = DeviceState::from_state(&app, &device);
+
+        let writes = edits(&known, &device, |s, d| set_mix(s, d, &sources[2], &mixes[0], "-6")).unwrap();
+        assert_eq!(writes, [(p.matrix_gain(1, &mixes[0]), ElemValue::Knob { db: -6.0, muted: false })]);
+
+        // something new goes on the first input that's switched off
+        let writes = edits(&known, &device, |s, d| set_mix(s, d, &sources[5], &mixes[1], "-10")).unwrap();
+        let input = p.matrix_input(2);
+        assert_eq!(writes, [
+            (input.clone(), ElemValue::Enum(device.enum_item(&input, &sources[5]).unwrap())),
+            (p.matrix_gain(2, &mixes[1]), ElemValue::Knob { db: -10.0, muted: false })
+        ]);
+    }
+
+    #[test]
+    fn values_are_parsed_for_the_kind_of_control() {
+        let device = device();
+        let p = device.profile;
+        let capture = p.capture(0);
+        let second = device.enum_items(&capture).unwrap()[1].clone();
+        assert_eq!(parse_value(&device, &capture, &second), Ok(ElemValue::Enum(1)));
+        assert_eq!(parse_value(&device, &capture, "1"), Ok(ElemValue::Enum(1)));
+        assert!(parse_value(&device, &capture, "Nowhere").is_err());
+
+        let global = p.global().unwrap();
+        assert!(matches!(parse_value(&device, &global, "-10dB"), Ok(ElemValue::Knob { db: -10.0, .. })));
+        assert!(matches!(parse_value(&device, &global, "mute"), Ok(ElemValue::Knob { muted: true, .. })));
+        assert!(parse_value(&device, "Nothing", "on").is_err());
+    }
+
+    #[test]
+    fn saved_state_falls_back_to_the_old_key() {
+        let device = device();
+        let mut state = device.known().to_state(&device);
+        let mut storage = SavedStorage::default();
+        state.global_gain = -12.0;
+        schema::store(&mut storage, eframe::APP_KEY, &state);
+        assert_eq!(app::saved_state(&storage, &device).unwrap().0.global_gain, -12.0);
+
+        state.global_gain = -3.0;
+        schema::store(&mut storage, &app::state_key(&device), &state);
+        assert_eq!(app::saved_state(&storage, &device).unwrap().0.global_gain, -3.0);
+    }
+}
@@ -0,0 +1,185 @@
+// scarlett-ctl daemon, which puts the mixer back whenever an interface turns up, since the first
+// generation forgets everything when it's switched off. what it's done goes in a status file that
+// `scarlett-ctl status` reads. see systemd/ for units that run it
+
+use std::{path::{Path, PathBuf}, sync::mpsc, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::{app::APP_ID, cli::saved_state, device::{CardWatcher, Device, DeviceInfo}};
+
+// how long to wait before trying an interface again, eg. when the driver hasn't finished setting it up
+// or the app hasn't saved anything for it yet
+const RETRY: Duration = Duration::from_secs(5);
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Status {
+    pid: u32,
+    // the scene or file being restored, or none for whatever the app last had
+    scene: Option<String>,
+    devices: Vec<DeviceStatus>
+}
+
+// an interface that's plugged in
+#[derive(serde::Deserialize, serde::Serialize)]
+struct DeviceStatus {
+    card: i32,
+    model: String,
+    id: String,
+    // when it was restored, in seconds since the epoch
+    restored: Option<u64>,
+    // why it couldn't be, the last time it was tried
+    error: Option<String>
+}
+
+fn status_path() -> PathBuf {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR").map_or_else(std::env::temp_dir, PathBuf::from);
+    dir.join(format!("{APP_ID}-daemon.json"))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// restore `scene` (or the app's saved state) on the interfaces called `card` (or all of them) as they
+// turn up, until killed
+pub fn run(card: Option<&str>, scene: Option<&str>) -> Result<String, String> {
+    let (tx, rx) = mpsc::channel();
+    let _watcher = CardWatcher::new(Box::new(move || {
+        let _ = tx.send(());
+    }));
+    let mut status = Status {
+        pid: std::process::id(),
+        scene: scene.map(str::to_owned),
+        devices: Vec::new()
+    };
+
+    loop {
+        let present: Vec<DeviceInfo> = Device::list().into_iter()
+            .filter_map(|d| d.map_err(|e| log::warn!("{e}")).ok())
+            .filter(|d| card.is_none_or(|c| d.is_called(c)))
+            .collect();
+        // unplugged ones are forgotten, so they're restored when they come back
+        status.devices.retain(|s| present.iter().any(|d| d.id == s.id && d.card == s.card));
+
+        for info in &present {
+            let i = match status.devices.iter().position(|s| s.id == info.id) {
+                Some(i) => i,
+                None => {
+                    status.devices.push(DeviceStatus {
+                        card: info.card,
+                        model: info.profile.name.to_owned(),
+                        id: info.id.clone(),
+                        restored: None,
+                        error: None
+                    });
+                    status.devices.len() - 1
+                }
+            };
+            let s = &mut status.devices[i];
+            if s.restored.is_some() {
+                continue;
+            }
+            match restore(info, scene) {
+                Ok(()) => {
+                    log::info!("restored {} ({})", info.profile.name, info.id);
+                    s.restored = Some(now());
+                    s.error = None;
+                }
+                Err(e) => {
+                    // it's tried again every few seconds, so only say when something's different
+                    if s.error.as_ref() != Some(&e) {
+                        log::warn!("couldn't restore {} ({}): {}", info.profile.name, info.id, e);
+                    }
+                    s.error = Some(e);
+                }
+            }
+        }
+
+        match serde_json::to_string_pretty(&status) {
+            Ok(text) => if let Err(e) = std::fs::write(status_path(), text) {
+                log::warn!("couldn't write {}: {}", status_path().display(), e);
+            },
+            Err(e) => log::warn!("couldn't write the status: {e}")
+        }
+
+        // wait for a card to come or go, or to try again
+        if status.devices.iter().any(|s| s.restored.is_none()) {
+            let _ = rx.recv_timeout(RETRY);
+        } else {
+            let _ = rx.recv();
+        }
+    }
+}
+
+fn restore(info: &DeviceInfo, scene: Option<&str>) -> Result<(), String> {
+    let mut device = Device::open(info).map_err(|e| e.to_string())?;
+    let state = saved_state(&device, scene)?;
+    device.update(&state).map_err(|e| e.to_string())?;
+    device.finish().map_err(|e| e.to_string())
+}
+
+pub fn status(json: bool) -> Result<String, String> {
+    let path = status_path();
+    let not_running = || "the daemon isn't running".to_owned();
+    let text = std::fs::read_to_string(&path).map_err(|_| not_running())?;
+    let status: Status = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    // left behind by one that's gone
+    if !Path::new(&format!("/proc/{}", status.pid)).exists() {
+        return Err(not_running());
+    }
+    if json {
+        serde_json::to_string(&status).map_err(|e| e.to_string())
+    } else {
+        Ok(describe(&status, now()))
+    }
+}
+
+fn describe(status: &Status, now: u64) -> String {
+    let restoring = status.scene.as_ref().map_or("the app's saved settings".to_owned(), |s| format!("\"{s}\""));
+    let mut lines = vec![format!("restoring {} (pid {})", restoring, status.pid)];
+    if status.devices.is_empty() {
+        lines.push("no interfaces are plugged in".to_owned());
+    }
+    for d in &status.devices {
+        let state = match (d.restored, &d.error) {
+            (Some(t), _) => format!("restored {} ago", ago(now.saturating_sub(t))),
+            (None, Some(e)) => format!("not restored: {e}"),
+            (None, None) => "not restored yet".to_owned()
+        };
+        lines.push(format!("hw:{}\t{}\t{}\t{}", d.card, d.model, d.id, state));
+    }
+    lines.join("\n")
+}
+
+fn ago(secs: u64) -> String {
+    match secs {
+        s if s < 60 => format!("{s}s"),
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h", s / 60 / 60),
+        s => format!("{}d", s / 24 / 60 / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_each_interface() {
+        let device = |card, restored, error: Option<&str>| DeviceStatus {
+            card,
+            model: "Scarlett 18i6".to_owned(),
+            id: format!("serial {card}"),
+            restored,
+            error: error.map(str::to_owned)
+        };
+        let status = Status {
+            pid: 10,
+            scene: Some("Live".to_owned()),
+            devices: vec![device(1, Some(1000), None), device(2, None, Some("permission denied"))]
+        };
+        assert_eq!(describe(&status, 1000 + 125), "\
+restoring \"Live\" (pid 10)
+hw:1\tScarlett 18i6\tserial 1\trestored 2m ago
+hw:2\tScarlett 18i6\tserial 2\tnot restored: permission denied");
+    }
+}
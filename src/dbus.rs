@@ -0,0 +1,363 @@
+// a session d-bus service, so desktop widgets and shortcut daemons can control the mixer while the app's
+// running and it's been turned on. it's /org/scarlettcontrol/Mixer on org.scarlettcontrol.ScarlettControl, with the
+// org.scarlettcontrol.Mixer interface:
+//
+//   properties  GlobalGain (d), GlobalMute (b), HiZ (ab), and read-only Outputs (a(sdb) of name, gain
+//               and mute), Scenes (as) and Scene (s, the one the settings match, or empty)
+//   methods     SetOutputGain(u output, d gain), SetOutputMute(u output, b mute),
+//               SetMixGain(u entry, u destination, d gain), ToggleGlobalMute(), LoadScene(s name)
+//
+// outputs, entries and destinations count from 1 like they do in the app, and gains are in dB.
+// PropertiesChanged is sent whenever any of them changes, from anywhere. eg.
+//
+//   busctl --user call org.scarlettcontrol.ScarlettControl /org/scarlettcontrol/Mixer org.scarlettcontrol.Mixer ToggleGlobalMute
+
+use std::sync::mpsc;
+
+use zbus::{blocking::connection, fdo};
+
+use crate::{app::AppState, device::{Device, GAIN_MIN}, fader::GAIN_MAX, state::Scene};
+
+const NAME: &str = "org.scarlettcontrol.ScarlettControl";
+const PATH: &str = "/org/scarlettcontrol/Mixer";
+
+// what the properties are read from, as of the last frame
+#[derive(Clone, PartialEq, Default, Debug)]
+struct Snapshot {
+    global_gain: f64,
+    global_mute: bool,
+    hi_z: Vec<bool>,
+    outputs: Vec<(String, f64, bool)>,
+    // the number of destinations of each entry
+    entries: Vec<usize>,
+    scenes: Vec<String>,
+    scene: String
+}
+
+impl Snapshot {
+    fn new(state: &AppState, scenes: &[Scene], device: &Device) -> Self {
+        Snapshot {
+            global_gain: state.global_gain.into(),
+            global_mute: state.global_mute,
+            hi_z: state.hi_z.clone(),
+            outputs: state.outputs.iter().map(|o| (o.name.clone(), o.gain.into(), o.mute)).collect(),
+            entries: state.mixer_entries.iter().map(|e| e.dests.len()).collect(),
+            scenes: scenes.iter().map(|s| s.name.clone()).collect(),
+            scene: current_scene(state, scenes, device).unwrap_or_default()
+        }
+    }
+
+    // what a property setter has already said the property is, once its `command` has been carried out
+    fn set(&mut self, command: &Command) {
+        match command {
+            Command::GlobalGain(db) => self.global_gain = (*db).into(),
+            Command::GlobalMute(mute) => self.global_mute = *mute,
+            Command::HiZ(on) => self.hi_z = on.clone(),
+            _ => {}
+        }
+    }
+}
+
+// the scene the settings are the same as, if there is one
+fn current_scene(state: &AppState, scenes: &[Scene], device: &Device) -> Option<String> {
+    scenes.iter()
+        .find(|s| {
+            let mut saved = s.state.clone();
+            saved.fit(device);
+            saved == *state
+        })
+        .map(|s| s.name.clone())
+}
+
+// something asked for over the bus, for the ui thread to do. indices count from 0
+#[derive(Clone, PartialEq, Debug)]
+enum Command {
+    GlobalGain(f32),
+    GlobalMute(bool),
+    ToggleGlobalMute,
+    HiZ(Vec<bool>),
+    OutputGain(usize, f32),
+    OutputMute(usize, bool),
+    MixGain(usize, usize, f32),
+    LoadScene(String)
+}
+
+// carry out `command`, returning false if there's nothing for it to do it to
+fn apply(state: &mut AppState, scenes: &[Scene], device: &Device, command: Command) -> bool {
+    match command {
+        Command::GlobalGain(db) => state.global_gain = db,
+        Command::GlobalMute(mute) => state.global_mute = mute,
+        Command::ToggleGlobalMute => state.global_mute = !state.global_mute,
+        Command::HiZ(on) if on.len() == state.hi_z.len() => state.hi_z = on,
+        Command::OutputGain(i, db) if i < state.outputs.len() => state.outputs[i].gain = db,
+        Command::OutputMute(i, mute) if i < state.outputs.len() => state.outputs[i].mute = mute,
+        Command::MixGain(i, j, db) => {
+            let Some(d) = state.mixer_entries.get_mut(i).and_then(|e| e.dests.get_mut(j)) else {
+                return false;
+            };
+            d.gain = db;
+        }
+        Command::LoadScene(name) => {
+            let Some(scene) = scenes.iter().find(|s| s.name == name) else {
+                return false;
+            };
+            let mut loaded = scene.state.clone();
+            loaded.fit(device);
+            *state = loaded;
+        }
+        _ => return false
+    }
+    true
+}
+
+fn gain(db: f64) -> f32 {
+    (db as f32).clamp(GAIN_MIN, GAIN_MAX)
+}
+
+// the index of something counting from 1, if there are `count` of them
+fn index(n: u32, count: usize, what: &str) -> fdo::Result<usize> {
+    (n as usize).checked_sub(1)
+        .filter(|i| *i < count)
+        .ok_or_else(|| fdo::Error::InvalidArgs(format!("there's no {what} {n}")))
+}
+
+struct Mixer {
+    snapshot: Snapshot,
+    commands: mpsc::Sender<Command>,
+    notify: Box<dyn Fn() + Send + Sync>
+}
+
+impl Mixer {
+    fn send(&self, command: Command) {
+        let _ = self.commands.send(command);
+        (self.notify)();
+    }
+}
+
+// setters change the snapshot too, so the PropertiesChanged sent straight away has the new value
+#[zbus::interface(name = "org.scarlettcontrol.Mixer")]
+impl Mixer {
+    #[zbus(property)]
+    fn global_gain(&self) -> f64 {
+        self.snapshot.global_gain
+    }
+
+    #[zbus(property)]
+    fn set_global_gain(&mut self, db: f64) {
+        let db = gain(db);
+        self.snapshot.global_gain = db.into();
+        self.send(Command::GlobalGain(db));
+    }
+
+    #[zbus(property)]
+    fn global_mute(&self) -> bool {
+        self.snapshot.global_mute
+    }
+
+    #[zbus(property)]
+    fn set_global_mute(&mut self, mute: bool) {
+        self.snapshot.global_mute = mute;
+        self.send(Command::GlobalMute(mute));
+    }
+
+    #[zbus(property)]
+    fn hi_z(&self) -> Vec<bool> {
+        self.snapshot.hi_z.clone()
+    }
+
+    #[zbus(property)]
+    fn set_hi_z(&mut self, on: Vec<bool>) -> fdo::Result<()> {
+        if on.len() != self.snapshot.hi_z.len() {
+            return Err(fdo::Error::InvalidArgs(format!("there are {} Hi-Z switches", self.snapshot.hi_z.len())));
+        }
+        self.snapshot.hi_z = on.clone();
+        self.send(Command::HiZ(on));
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn outputs(&self) -> Vec<(String, f64, bool)> {
+        self.snapshot.outputs.clone()
+    }
+
+    #[zbus(property)]
+    fn scenes(&self) -> Vec<String> {
+        self.snapshot.scenes.clone()
+    }
+
+    #[zbus(property)]
+    fn scene(&self) -> String {
+        self.snapshot.scene.clone()
+    }
+
+    fn set_output_gain(&self, output: u32, db: f64) -> fdo::Result<()> {
+        let i = index(output, self.snapshot.outputs.len(), "output")?;
+        self.send(Command::OutputGain(i, gain(db)));
+        Ok(())
+    }
+
+    fn set_output_mute(&self, output: u32, mute: bool) -> fdo::Result<()> {
+        let i = index(output, self.snapshot.outputs.len(), "output")?;
+        self.send(Command::OutputMute(i, mute));
+        Ok(())
+    }
+
+    fn set_mix_gain(&self, entry: u32, destination: u32, db: f64) -> fdo::Result<()> {
+        let i = index(entry, self.snapshot.entries.len(), "mixer entry")?;
+        let j = index(destination, self.snapshot.entries[i], "destination")?;
+        self.send(Command::MixGain(i, j, gain(db)));
+        Ok(())
+    }
+
+    fn toggle_global_mute(&self) {
+        self.send(Command::ToggleGlobalMute);
+    }
+
+    fn load_scene(&self, name: String) -> fdo::Result<()> {
+        if !self.snapshot.scenes.contains(&name) {
+            return Err(fdo::Error::InvalidArgs(format!("there's no scene called \"{name}\"")));
+        }
+        self.send(Command::LoadScene(name));
+        Ok(())
+    }
+}
+
+pub struct DbusService {
+    connection: zbus::blocking::Connection,
+    commands: mpsc::Receiver<Command>,
+    // what the properties were last set from
+    state: AppState,
+    scenes: Vec<Scene>,
+    sent: Snapshot
+}
+
+impl DbusService {
+    // take the name on the session bus. `notify` is called from another thread when something's asked for
+    pub fn new(notify: Box<dyn Fn() + Send + Sync>) -> zbus::Result<DbusService> {
+        let (tx, commands) = mpsc::channel();
+        let mixer = Mixer { snapshot: Snapshot::default(), commands: tx, notify };
+        let connection = connection::Builder::session()?
+            .name(NAME)?
+            .serve_at(PATH, mixer)?
+            .build()?;
+        Ok(DbusService {
+            connection,
+            commands,
+            state: AppState::default(),
+            scenes: Vec::new(),
+            sent: Snapshot::default()
+        })
+    }
+
+    // do what's been asked for since the last call
+    pub fn receive(&mut self, state: &mut AppState, scenes: &[Scene], device: &Device) {
+        for command in self.commands.try_iter() {
+            log::debug!("d-bus asked for {:?}", command);
+            // zbus sent PropertiesChanged for setters already, so don't send it again for them
+            if apply(state, scenes, device, command.clone()) {
+                self.sent.set(&command);
+            }
+        }
+    }
+
+    // update the properties from `state`, and say which have changed
+    pub fn send(&mut self, state: &AppState, scenes: &[Scene], device: &Device) {
+        if self.state == *state && self.scenes == scenes {
+            return;
+        }
+        self.state = state.clone();
+        self.scenes = scenes.to_vec();
+        let snapshot = Snapshot::new(state, scenes, device);
+        if let Err(e) = self.changed(snapshot) {
+            log::warn!("couldn't update the d-bus properties: {}", e);
+        }
+    }
+
+    fn changed(&mut self, snapshot: Snapshot) -> zbus::Result<()> {
+        let iface = self.connection.object_server().interface::<_, Mixer>(PATH)?;
+        iface.get_mut().snapshot = snapshot.clone();
+        let old = std::mem::replace(&mut self.sent, snapshot);
+        let new = &self.sent;
+        let mixer = iface.get();
+        let ctxt = iface.signal_context();
+        zbus::block_on(async {
+            if old.global_gain != new.global_gain {
+                mixer.global_gain_changed(ctxt).await?;
+            }
+            if old.global_mute != new.global_mute {
+                mixer.global_mute_changed(ctxt).await?;
+            }
+            if old.hi_z != new.hi_z {
+                mixer.hi_z_changed(ctxt).await?;
+            }
+            if old.outputs != new.outputs {
+                mixer.outputs_changed(ctxt).await?;
+            }
+            if old.scenes != new.scenes {
+                mixer.scenes_changed(ctxt).await?;
+            }
+            if old.scene != new.scene {
+                mixer.scene_changed(ctxt).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{profile::DeviceProfile, state::MixerEntry};
+
+    use super::*;
+
+    fn device() -> (Device, AppState) {
+        let device = Device::simulated(DeviceProfile::find("Scarlett 18i6").unwrap());
+        let mut state = device.known().to_state(&device);
+        state.mixer_entries = vec![MixerEntry::new(&device)];
+        (device, state)
+    }
+
+    #[test]
+    fn scenes_are_loaded_and_recognised() {
+        let (device, mut state) = device();
+        let mut quiet = state.clone();
+        quiet.global_gain = -30.0;
+        let scenes = [Scene { name: "Quiet".to_owned(), state: quiet }];
+        assert_eq!(Snapshot::new(&state, &scenes, &device).scene, "");
+
+        assert!(apply(&mut state, &scenes, &device, Command::LoadScene("Quiet".to_owned())));
+        assert!(!apply(&mut state, &scenes, &device, Command::LoadScene("Loud".to_owned())));
+        let snapshot = Snapshot::new(&state, &scenes, &device);
+        assert_eq!((snapshot.scene.as_str(), snapshot.global_gain), ("Quiet", -30.0));
+
+        assert!(apply(&mut state, &scenes, &device, Command::OutputMute(0, true)));
+        assert!(!apply(&mut state, &scenes, &device, Command::OutputMute(10, true)));
+        assert!(!apply(&mut state, &scenes, &device, Command::HiZ(vec![true; 10])));
+        let snapshot = Snapshot::new(&state, &scenes, &device);
+        assert!(snapshot.outputs[0].2);
+        assert_eq!(snapshot.scene, "");
+    }
+
+    #[test]
+    fn setters_are_only_announced_once() {
+        let (device, mut state) = device();
+        let mut sent = Snapshot::new(&state, &[], &device);
+        for command in [Command::GlobalGain(-20.0), Command::ToggleGlobalMute, Command::HiZ(vec![true, false])] {
+            assert!(apply(&mut state, &[], &device, command.clone()));
+            sent.set(&command);
+        }
+        let snapshot = Snapshot::new(&state, &[], &device);
+        assert_eq!((sent.global_gain, &sent.hi_z), (snapshot.global_gain, &snapshot.hi_z));
+        // toggling isn't a setter, so it still needs announcing
+        assert_ne!(sent.global_mute, snapshot.global_mute);
+    }
+
+    #[test]
+    fn indices_count_from_one() {
+        assert_eq!(index(1, 2, "output").unwrap(), 0);
+        assert_eq!(index(2, 2, "output").unwrap(), 1);
+        for n in [0, 3] {
+            assert!(matches!(index(n, 2, "output"), Err(fdo::Error::InvalidArgs(_))));
+        }
+    }
+}
@@ -1,51 +1,95 @@
-use std::{collections::HashMap, ops::{Deref, DerefMut}};
+use std::{collections::{HashMap, HashSet}, fmt, ops::{Deref, DerefMut}, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::Duration};
 
-use alsa::mixer::{MilliBel, Selem, SelemChannelId};
-
-use crate::ScarlettControlApp;
-
-const CHANNEL: SelemChannelId = SelemChannelId::FrontLeft /*SelemChannelId::mono()*/;
+use crate::{app::AppState, backend::{AlsaBackend, MixerBackend, SimulatedBackend}, fader::GAIN_MAX, profile::DeviceProfile, state::{new_id, MixerDestination, MixerEntry, MixerOutput, PanLaw, Port}, writer::WriteQueue, ScarlettControlApp};
 
 pub type EnumIndex = usize;
 
-#[derive(PartialEq)]
-enum ElemValue {
+// lowest gain the matrix accepts, which the driver treats as silence
+pub const GAIN_MIN: f32 = -128.0;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum ElemValue {
     Enum(EnumIndex),
-    Knob { db: f32, muted: bool }
+    Knob { db: f32, muted: bool },
+    Switch(bool)
 }
 
-impl<'a> From<&Selem<'a>> for ElemValue {
-    fn from(value: &Selem) -> Self {
-        if value.is_enumerated() {
-            ElemValue::Enum(value.get_enum_item(CHANNEL).unwrap() as EnumIndex)
-        } else {
-            ElemValue::Knob {
-                db: value.get_playback_vol_db(CHANNEL).unwrap().to_db(),
-                muted: value.get_playback_switch(CHANNEL).unwrap() == 0
-            }
+#[derive(PartialEq, Clone, Debug)]
+pub enum DeviceError {
+    // the card isn't there, or went away (eg. "hw:1")
+    CardNotFound(String),
+    ControlMissing(String),
+    OutOfRange { control: String, value: ElemValue },
+    Io(String),
+    PermissionDenied(String)
+}
+
+impl DeviceError {
+    // work out what went wrong from an alsa error while using `target` (a card or control)
+    pub fn alsa(target: &str, e: alsa::Error) -> Self {
+        // some calls give back negative codes
+        match e.errno().abs() {
+            libc::ENODEV | libc::ENOENT | libc::ENXIO => DeviceError::CardNotFound(target.to_owned()),
+            libc::EACCES | libc::EPERM => DeviceError::PermissionDenied(target.to_owned()),
+            _ => DeviceError::Io(format!("{}: {}", target, e))
         }
     }
-}
 
-trait ElemSettable {
-    fn set_value(&self, val: &ElemValue);
+    // whether the device can't be used at all any more, rather than just one control
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, DeviceError::CardNotFound(_) | DeviceError::PermissionDenied(_))
+    }
 }
 
-impl ElemSettable for Selem<'_> {
-    fn set_value(&self, val: &ElemValue) {
-        match val {
-            ElemValue::Enum(val) => self.set_enum_item(CHANNEL, *val as u32).unwrap(),
-            ElemValue::Knob { db, muted } => {
-                self.set_playback_db(CHANNEL, MilliBel::from_db(*db),
-                    if self.has_playback_channel(CHANNEL) { alsa::Round::Floor } else { alsa::Round::Ceil }
-                ).unwrap();
-                self.set_playback_switch(CHANNEL, if *muted { 0 } else { 1 }).unwrap()
-            },
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::CardNotFound(card) => write!(f, "{} isn't connected", card),
+            DeviceError::ControlMissing(control) => write!(f, "the interface has no control called \"{}\"", control),
+            DeviceError::OutOfRange { control, value } => write!(f, "{:?} is out of range for \"{}\"", value, control),
+            DeviceError::Io(e) => write!(f, "I/O error: {}", e),
+            DeviceError::PermissionDenied(target) => write!(f, "permission denied for {} (is your user in the audio group?)", target)
         }
     }
 }
 
-struct DeviceState(HashMap<String, ElemValue>);
+impl std::error::Error for DeviceError {}
+
+// what a control does, so changes to it can be traced back to the app state
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Control {
+    Capture(usize),
+    MatrixInput(usize),
+    // (matrix input, mix)
+    MatrixGain(usize, usize),
+    Global,
+    OutputGain(usize),
+    OutputSource(usize),
+    // index into the profile's list of inputs with the switch
+    HiZ(usize),
+    Pad(usize),
+    Air(usize)
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceState(HashMap<String, ElemValue>);
+
+// how one device state differs from another, with the keys of each kind sorted
+#[derive(PartialEq, Debug, Default)]
+pub struct StateDiff {
+    // in both, set to different values
+    pub changed: Vec<String>,
+    // only in the new state
+    pub added: Vec<String>,
+    // only in the old state
+    pub removed: Vec<String>
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.added.is_empty() && self.removed.is_empty()
+    }
+}
 
 impl Deref for DeviceState {
     type Target = HashMap<String, ElemValue>;
@@ -66,103 +110,1077 @@ impl DeviceState {
         DeviceState(HashMap::new())
     }
 
-    // identify keys in both `self` and `new` where their values differ
-    pub fn diff(&self, new: &DeviceState) -> Vec<String> {
-        self.iter().filter_map(|(k, v)| new.get(k).filter(|v2| v == *v2)
-            .map(|_| k.clone())).collect()
+    // what would have to change to get from `self` to `new`
+    pub fn diff(&self, new: &DeviceState) -> StateDiff {
+        let mut d = StateDiff::default();
+        for (k, v) in new.iter() {
+            match self.get(k) {
+                Some(old) if old != v => d.changed.push(k.clone()),
+                Some(_) => {}
+                None => d.added.push(k.clone())
+            }
+        }
+        d.removed = self.keys().filter(|k| !new.contains_key(*k)).cloned().collect();
+        d.changed.sort();
+        d.added.sort();
+        d.removed.sort();
+        d
     }
 }
 
-impl From<&ScarlettControlApp> for DeviceState {
-    fn from(a: &ScarlettControlApp) -> Self {
+impl DeviceState {
+    pub fn from_state(s: &AppState, device: &Device) -> Self {
+        DeviceState::from_state_at(s, device, &matrix_layout(s, device.profile.matrix_inputs))
+    }
+
+    // the same with the entries on the matrix inputs in `layout`, as (entry index, first input)
+    pub fn from_state_at(s: &AppState, device: &Device, layout: &[(usize, usize)]) -> Self {
+        let p = device.profile;
         let mut d = DeviceState::new();
 
         // capture
-        for (i, v) in a.state.capture.iter().enumerate() {
-            d.insert(format!("Input Source {:02}", i), ElemValue::Enum(0)); // TODO
+        for (i, v) in s.capture.iter().enumerate().take(p.capture_channels) {
+            let control = p.capture(i);
+            let item = device.source_item(&control, v.as_ref());
+            d.insert(control, ElemValue::Enum(item));
         }
 
         // mixer
-        for entry in &a.state.mixer_entries {
+        // everything not used by an entry is switched off
+        for n in 0..p.matrix_inputs {
+            let control = p.matrix_input(n);
+            let off = device.source_item(&control, None);
+            d.insert(control, ElemValue::Enum(off));
+            for mix in &device.mixer_destinations {
+                d.insert(p.matrix_gain(n, mix), ElemValue::Knob { db: GAIN_MIN, muted: false });
+            }
+        }
+
+        let soloed = soloed_in(s, layout);
+        for &(i, input) in layout {
+            let entry = &s.mixer_entries[i];
+            let sources = if entry.stereo { vec![&entry.source, &entry.source_r] } else { vec![&entry.source] };
+            for (c, src) in sources.iter().enumerate() {
+                let control = p.matrix_input(input + c);
+                let item = device.source_item(&control, Some(src));
+                d.insert(control, ElemValue::Enum(item));
+            }
+
             for dest in &entry.dests {
-                match (entry.stereo, dest.stereo) {
-                    (true, true) => {
-                        // d.insert(format!("Matrix {:02} Mix {}", ))
+                for (c, mix, gain) in route_gains(entry.stereo, dest, s.pan_law) {
+                    // mixes this device doesn't have go nowhere, and muted ones are left switched off
+                    if mix.resolve(&device.mixer_destinations).is_some() && audible(entry, dest, mix, &soloed) {
+                        d.insert(p.matrix_gain(input + c, &mix.0), ElemValue::Knob { db: gain, muted: false });
                     }
-                    (true, false) => todo!(),
-                    (false, true) => todo!(),
-                    (false, false) => todo!(),
                 }
             }
         }
 
         // global state
+        if let Some(global) = p.global() {
+            d.insert(global, ElemValue::Knob { db: s.global_gain, muted: s.global_mute });
+        }
 
-        // hi z
+        // input switches
+        for (n, on) in p.hi_z.iter().zip(&s.hi_z) {
+            let control = p.hi_z(*n);
+            let v = device.switch_value(&control, *on, p.names.hi_z_on);
+            d.insert(control, v);
+        }
+        for (n, on) in p.pad.iter().zip(&s.pad) {
+            let control = p.pad(*n);
+            let v = device.switch_value(&control, *on, p.names.pad_on);
+            d.insert(control, v);
+        }
+        for (n, on) in p.air.iter().zip(&s.air) {
+            d.insert(p.air(*n), ElemValue::Switch(*on));
+        }
 
         // outputs
+        for (i, o) in s.outputs.iter().enumerate().take(p.outputs.len()) {
+            for control in p.output_gains(i) {
+                d.insert(control, ElemValue::Knob { db: o.gain, muted: o.mute });
+            }
+            let (left, right) = (p.output_source(i, false), p.output_source(i, true));
+            let (l, r) = (device.source_item(&left, Some(&o.source.0)), device.source_item(&right, Some(&o.source.1)));
+            d.insert(left, ElemValue::Enum(l));
+            d.insert(right, ElemValue::Enum(r));
+        }
 
+        // don't try to write controls this particular device doesn't have
+        d.retain(|k, _| device.controls.contains(k));
         d
     }
 }
 
-impl From<&Device> for DeviceState {
-    fn from(d: &Device) -> Self {
-        Self (HashMap::from_iter(
-            d.selems.iter()
-                .map(|(k, s)| (k.clone(), ElemValue::from(s)))
-        ))
+impl DeviceState {
+    // whether every control in `other` is set the same way here, give or take the hardware's rounding
+    pub fn agrees_with(&self, other: &DeviceState) -> bool {
+        other.iter().all(|(k, v)| self.get(k).is_some_and(|v1| same_value(v1, v)))
+    }
+
+    pub fn to_state(&self, device: &Device) -> AppState {
+        self.to_state_at(device).0
+    }
+
+    // the same, and the matrix input each entry was read from, like `matrix_layout`. inputs nobody can
+    // hear are left out, so these can have gaps where the app has muted entries
+    pub fn to_state_at(&self, device: &Device) -> (AppState, Vec<(usize, usize)>) {
+        let p = device.profile;
+        // label the enum `control` is currently set to
+        let label = |control: &str| match self.get(control) {
+            Some(ElemValue::Enum(i)) => device.enums.get(control).and_then(|items| items.get(*i)).map(|l| l.as_str()),
+            _ => None
+        };
+        let source = |control: &str| label(control).and_then(|l| device.audio_sources.iter().position(|s| s == l));
+        let knob = |control: &str| match self.get(control) {
+            Some(ElemValue::Knob { db, muted }) => (*db, *muted),
+            _ => (0.0, false)
+        };
+        let switch = |control: &str, on_label: &str| match self.get(control) {
+            Some(ElemValue::Switch(on)) => *on,
+            _ => label(control) == Some(on_label)
+        };
+
+        // capture
+        let capture = (0..p.capture_channels)
+            .map(|i| source(&p.capture(i)).map(|s| Port::at(&device.audio_sources, s)))
+            .collect();
+
+        // mixer
+        // the source of each matrix input and its gain into each mix, leaving out inputs nobody can hear
+        let inputs: Vec<Option<(EnumIndex, Vec<Option<f32>>)>> = (0..p.matrix_inputs).map(|n| {
+            let src = source(&p.matrix_input(n))?;
+            let gains: Vec<Option<f32>> = device.mixer_destinations.iter()
+                .map(|mix| Some(knob(&p.matrix_gain(n, mix)).0).filter(|db| *db > GAIN_MIN))
+                .collect();
+            gains.iter().any(Option::is_some).then_some((src, gains))
+        }).collect();
+
+        let source_name = |s: EnumIndex| device.audio_sources[s].clone();
+        let port = |s: EnumIndex| Port::at(&device.audio_sources, s);
+        let mixes = &device.mixer_destinations;
+        let mut mixer_entries = Vec::new();
+        let mut layout = Vec::new();
+        let mut n = 0;
+        while n < inputs.len() {
+            let Some((src, gains)) = &inputs[n] else {
+                n += 1;
+                continue;
+            };
+            // adjacent inputs playing a left/right pair with matching gains were a stereo entry
+            let stereo = inputs.get(n + 1).and_then(|r| r.as_ref())
+                .filter(|(src_r, _)| *src_r == src + 1)
+                .and_then(|(src_r, gains_r)| stereo_dests(gains, gains_r, mixes).map(|dests| (*src_r, dests)));
+
+            layout.push((mixer_entries.len(), n));
+            n += if stereo.is_some() { 2 } else { 1 };
+            mixer_entries.push(match stereo {
+                Some((src_r, dests)) => MixerEntry {
+                    name: format!("{} / {}", source_name(*src), source_name(src_r)),
+                    enabled: true,
+                    mute: false,
+                    solo: false,
+                    stereo: true,
+                    split: !is_pair(*src, src_r),
+                    source: port(*src),
+                    source_r: port(src_r),
+                    dests,
+                    id: new_id()
+                },
+                None => MixerEntry {
+                    name: source_name(*src),
+                    enabled: true,
+                    mute: false,
+                    solo: false,
+                    stereo: false,
+                    split: false,
+                    source: port(*src),
+                    source_r: port(src + 1),
+                    dests: mono_dests(gains, mixes),
+                    id: new_id()
+                }
+            });
+        }
+
+        // outputs
+        let outputs = p.outputs.iter().enumerate().map(|(i, name)| {
+            let (gain, mute) = knob(&p.output_gains(i)[0]);
+            let l = source(&p.output_source(i, false)).unwrap_or(0);
+            let r = source(&p.output_source(i, true)).unwrap_or(l + 1);
+            MixerOutput {
+                name: (*name).to_owned(),
+                gain,
+                mute,
+                source: (Port::at(&device.audio_sources, l), Port::at(&device.audio_sources, r)),
+                split: !is_pair(l, r)
+            }
+        }).collect();
+
+        let (global_gain, global_mute) = p.global().map_or((0.0, false), |g| knob(&g));
+        let state = AppState {
+            capture,
+            mixer_entries,
+            global_gain,
+            global_mute,
+            hi_z: p.hi_z.iter().map(|n| switch(&p.hi_z(*n), p.names.hi_z_on)).collect(),
+            pad: p.pad.iter().map(|n| switch(&p.pad(*n), p.names.pad_on)).collect(),
+            air: p.air.iter().map(|n| switch(&p.air(*n), "")).collect(),
+            outputs,
+            // the matrix only has gains, so everything read from it is in the middle and the law doesn't matter
+            pan_law: PanLaw::default()
+        };
+        (state, layout)
+    }
+
+    // where the entries of `s` go when the ones in `placed` (from `to_state_at`) stay on their inputs.
+    // the rest take the first inputs that are switched off here, or there's none if they don't fit
+    pub fn layout_around(&self, s: &AppState, placed: &[(usize, usize)], device: &Device) -> Option<Vec<(usize, usize)>> {
+        let p = device.profile;
+        let channels = |i: usize| if s.mixer_entries[i].stereo { 2 } else { 1 };
+        let mut free: Vec<bool> = (0..p.matrix_inputs).map(|n| {
+            let control = p.matrix_input(n);
+            self.get(&control) == Some(&ElemValue::Enum(device.source_item(&control, None)))
+        }).collect();
+        for &(i, input) in placed {
+            free[input..input + channels(i)].fill(false);
+        }
+        let mut layout = placed.to_vec();
+        for (i, _) in s.mixer_entries.iter().enumerate().filter(|(i, e)| e.enabled && !placed.iter().any(|(j, _)| j == i)) {
+            let input = free.windows(channels(i)).position(|w| w.iter().all(|f| *f))?;
+            free[input..input + channels(i)].fill(false);
+            layout.push((i, input));
+        }
+        Some(layout)
+    }
+}
+
+// the first matrix input of each enabled entry that fits, as (entry index, input)
+// entries take consecutive matrix inputs, one for mono and two for stereo
+pub fn matrix_layout(s: &AppState, inputs: usize) -> Vec<(usize, usize)> {
+    let mut layout = Vec::new();
+    let mut input = 0;
+    for (i, entry) in s.mixer_entries.iter().enumerate().filter(|(_, e)| e.enabled) {
+        let channels = if entry.stereo { 2 } else { 1 };
+        if input + channels > inputs {
+            break;
+        }
+        layout.push((i, input));
+        input += channels;
+    }
+    layout
+}
+
+// (entry channel, mix) pairs fed by a destination of an entry
+pub fn routes(stereo: bool, dest: &MixerDestination) -> Vec<(usize, &Port)> {
+    match (stereo, dest.stereo) {
+        (true, true) => vec![(0, &dest.dest), (1, &dest.dest_r)],
+        // fold down: both channels into the one mix
+        (true, false) => vec![(0, &dest.dest), (1, &dest.dest)],
+        // fan out: the one channel into both mixes
+        (false, true) => vec![(0, &dest.dest), (0, &dest.dest_r)],
+        (false, false) => vec![(0, &dest.dest)],
     }
 }
 
-const CARD_NAME: &str = "Scarlett 18i6";
+// mixes that something is soloed into, which only what's soloed can be heard in
+pub fn soloed_mixes(s: &AppState, inputs: usize) -> HashSet<&Port> {
+    soloed_in(s, &matrix_layout(s, inputs))
+}
 
-type Selems<'a> = HashMap<String, Selem<'a>>;
+// the same for the entries in `layout`
+fn soloed_in<'a>(s: &'a AppState, layout: &[(usize, usize)]) -> HashSet<&'a Port> {
+    layout.iter()
+        .map(|(i, _)| &s.mixer_entries[*i])
+        .flat_map(|e| e.dests.iter().filter(|d| e.solo || d.solo).flat_map(|d| routes(e.stereo, d)))
+        .map(|(_, mix)| mix)
+        .collect()
+}
+
+// the muting model, which everything that reads or writes the matrix goes by. the matrix has no mute, so
+// a route that can't be heard is written as GAIN_MIN and the destination keeps its gain for when it can be
+// again. it can't be heard when the destination or its entry is muted, or when something else is soloed into
+// the mix and neither of them is. a route turned up from elsewhere (alsamixer, say) unmutes the destination
+// and its entry, and one turned down while it could be heard takes the new gain; soloing is left as it is.
+//
+// whether `dest` of `entry` can be heard in `mix`, given the mixes from `soloed_mixes`
+pub fn audible(entry: &MixerEntry, dest: &MixerDestination, mix: &Port, soloed: &HashSet<&Port>) -> bool {
+    !entry.mute && !dest.mute && (entry.solo || dest.solo || !soloed.contains(mix))
+}
+
+// the same with the gain into each mix, after the destination's pan or balance
+pub fn route_gains(stereo: bool, dest: &MixerDestination, law: PanLaw) -> Vec<(usize, &Port, f32)> {
+    routes(stereo, dest).into_iter()
+        .zip(dest.pan_offsets(stereo, law))
+        .map(|((c, mix), offset)| (c, mix, (dest.gain + offset).clamp(GAIN_MIN, GAIN_MAX)))
+        .collect()
+}
+
+fn copy_at(to: &mut [bool], from: &[bool], i: usize) {
+    if let (Some(t), Some(f)) = (to.get_mut(i), from.get(i)) {
+        *t = *f;
+    }
+}
+
+// equal, give or take the hardware's rounding of gains
+fn same_value(a: &ElemValue, b: &ElemValue) -> bool {
+    match (a, b) {
+        (ElemValue::Knob { db, muted }, ElemValue::Knob { db: db2, muted: muted2 }) =>
+            muted == muted2 && same_gain(*db, *db2),
+        (a, b) => a == b
+    }
+}
+
+// the hardware only has whole dB steps
+fn same_gain(a: f32, b: f32) -> bool {
+    (a - b).abs() < 1.0
+}
+
+// whether `l` and `r` are one of the left/right pairs offered when a stereo selection isn't split
+fn is_pair(l: EnumIndex, r: EnumIndex) -> bool {
+    l.is_multiple_of(2) && r == l + 1
+}
+
+// the same for ports, which aren't a pair if either isn't in `labels`
+pub fn is_port_pair(l: &Port, r: &Port, labels: &[String]) -> bool {
+    matches!((l.resolve(labels), r.resolve(labels)), (Some(l), Some(r)) if is_pair(l, r))
+}
+
+fn destination(stereo: bool, dest: EnumIndex, dest_r: EnumIndex, gain: f32, mixes: &[String]) -> MixerDestination {
+    MixerDestination {
+        stereo,
+        dest: Port::at(mixes, dest),
+        dest_r: Port::at(mixes, dest_r),
+        split: stereo && !is_pair(dest, dest_r),
+        gain,
+        mute: false,
+        pan: 0.0,
+        solo: false,
+        id: new_id()
+    }
+}
+
+// destinations of a mono matrix input from its gain into each mix
+fn mono_dests(gains: &[Option<f32>], mixes: &[String]) -> Vec<MixerDestination> {
+    let mut dests = Vec::new();
+    let mut m = 0;
+    while m < gains.len() {
+        match (gains[m], gains.get(m + 1).copied().flatten()) {
+            // the same level into a left/right pair of mixes is a fan out to a stereo destination
+            (Some(a), Some(b)) if is_pair(m, m + 1) && same_gain(a, b) => {
+                dests.push(destination(true, m, m + 1, a, mixes));
+                m += 2;
+            }
+            (Some(a), _) => {
+                dests.push(destination(false, m, m + 1, a, mixes));
+                m += 1;
+            }
+            (None, _) => m += 1
+        }
+    }
+    dests
+}
+
+// destinations of a pair of matrix inputs, if they can be explained as a single stereo entry
+fn stereo_dests(l: &[Option<f32>], r: &[Option<f32>], mixes: &[String]) -> Option<Vec<MixerDestination>> {
+    let (mut l, mut r) = (l.to_vec(), r.to_vec());
+    let mut dests = Vec::new();
+
+    // both channels into one mix at the same level is a fold down to a mono destination
+    for m in 0..l.len() {
+        if let (Some(a), Some(b)) = (l[m], r[m]) {
+            if same_gain(a, b) {
+                dests.push(destination(false, m, m + 1, a, mixes));
+                l[m] = None;
+                r[m] = None;
+            }
+        }
+    }
+
+    // whatever is left has to line up as left mix, right mix
+    let left: Vec<(EnumIndex, f32)> = l.iter().enumerate().filter_map(|(m, g)| g.map(|g| (m, g))).collect();
+    let right: Vec<(EnumIndex, f32)> = r.iter().enumerate().filter_map(|(m, g)| g.map(|g| (m, g))).collect();
+    if left.len() != right.len() {
+        return None;
+    }
+    for ((ml, gl), (mr, gr)) in left.into_iter().zip(right) {
+        if !same_gain(gl, gr) {
+            return None;
+        }
+        dests.push(destination(true, ml, mr, gl, mixes));
+    }
+
+    dests.sort_by_key(|d| d.dest.resolve(mixes));
+    Some(dests)
+}
+
+impl From<&ScarlettControlApp> for DeviceState {
+    fn from(a: &ScarlettControlApp) -> Self {
+        DeviceState::from_state(&a.state, &a.device)
+    }
+}
+
+impl TryFrom<&Device> for DeviceState {
+    type Error = DeviceError;
+
+    fn try_from(d: &Device) -> Result<Self, DeviceError> {
+        let backend = d.backend();
+        backend.controls().into_iter()
+            .map(|k| {
+                let v = backend.get_value(&k)?;
+                Ok((k, v))
+            })
+            .collect::<Result<_, _>>()
+            .map(DeviceState)
+    }
+}
+
+// a supported interface that's plugged in
+#[derive(Clone)]
+pub struct DeviceInfo {
+    pub card: i32,
+    pub name: String,
+    pub longname: String,
+    // usb serial number if it can be found, otherwise the long name (which has the usb port in it)
+    pub id: String,
+    pub profile: &'static DeviceProfile
+}
+
+impl DeviceInfo {
+    // whether `name` is the card's number (with or without "hw:"), name or id
+    pub fn is_called(&self, name: &str) -> bool {
+        self.card.to_string() == name || format!("hw:{}", self.card) == name || self.name == name || self.id == name
+    }
+}
+
+// usb serial number of a card, from sysfs
+fn usb_serial(card: i32) -> Option<String> {
+    std::fs::read_to_string(format!("/sys/class/sound/card{}/device/../serial", card)).ok()
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+}
+
+// how often to look for cards being plugged in or unplugged
+const HOTPLUG_INTERVAL: Duration = Duration::from_secs(1);
+
+// every card, by index and long name (which has the usb port in it, so a different interface on the same index shows up)
+fn cards() -> Vec<(i32, String)> {
+    alsa::card::Iter::new().flatten()
+        .map(|c| (c.get_index(), c.get_longname().unwrap_or_default()))
+        .collect()
+}
+
+// notices cards being added or removed, by polling since alsa doesn't say when that happens
+pub struct CardWatcher(Arc<AtomicBool>);
+
+impl CardWatcher {
+    // `notify` is called from a background thread when something changes
+    pub fn new(notify: Box<dyn Fn() + Send>) -> CardWatcher {
+        let changed = Arc::new(AtomicBool::new(false));
+        let weak = Arc::downgrade(&changed);
+        std::thread::spawn(move || {
+            let mut last = cards();
+            loop {
+                std::thread::sleep(HOTPLUG_INTERVAL);
+                // stop once the watcher is gone
+                let Some(changed) = weak.upgrade() else {
+                    break;
+                };
+                let now = cards();
+                if now != last {
+                    changed.store(true, Ordering::Relaxed);
+                    notify();
+                    last = now;
+                }
+            }
+        });
+        CardWatcher(changed)
+    }
+
+    // whether any cards came or went since the last call
+    pub fn changed(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
 
 pub struct Device {
-    pub selems: Selems<'static>,
+    // the writer thread has its own handle, so this one's never held up by writes
+    backend: Box<dyn MixerBackend>,
+    writer: WriteQueue,
+    pub profile: &'static DeviceProfile,
+    // identifies the physical device, for keeping a separate saved state for each one
+    pub id: String,
+    // names of every control, and the labels of the enumerated ones
+    controls: HashSet<String>,
+    enums: HashMap<String, Vec<String>>,
+    // what each control the profile knows about does
+    roles: HashMap<String, Control>,
     pub capture_sources: Vec<String>,
     // audio sources for mixer entries and outputs
     pub audio_sources: Vec<String>,
     // mixes that a mixer entry can send audio to
-    pub mixer_destinations: Vec<String>
+    pub mixer_destinations: Vec<String>,
+    // every control's value as last seen, to work out what something else changed
+    known: DeviceState,
+    // what we last asked each control to be, which the hardware may have rounded
+    written: DeviceState,
+    // controls changed by something else that haven't made it into the app state yet
+    changed: HashSet<String>
 }
 
-fn get_enums(selem: &Selem) -> Vec<String> {
-    selem.iter_enum().unwrap().map(|i| i.unwrap()).collect()
+impl Device {
+    // every supported interface that's plugged in, or why it couldn't be looked at
+    pub fn list() -> Vec<Result<DeviceInfo, DeviceError>> {
+        alsa::card::Iter::new().filter_map(|r| match r {
+            Ok(c) => Device::info(&c).transpose(),
+            Err(e) => Some(Err(DeviceError::alsa("sound cards", e)))
+        }).collect()
+    }
+
+    // details of `card`, if it's an interface we support
+    fn info(card: &alsa::Card) -> Result<Option<DeviceInfo>, DeviceError> {
+        // not being able to read the name of some other card isn't our problem
+        let Ok(name) = card.get_name() else {
+            return Ok(None);
+        };
+        if !crate::profile::PROFILES.iter().any(|p| p.card_name == name) {
+            return Ok(None);
+        }
+        let Some(profile) = DeviceProfile::detect(&name, &AlsaBackend::open(card)?.controls()) else {
+            return Ok(None);
+        };
+        let longname = card.get_longname().map_err(|e| DeviceError::alsa(&name, e))?;
+        Ok(Some(DeviceInfo {
+            card: card.get_index(),
+            id: usb_serial(card.get_index()).unwrap_or_else(|| longname.clone()),
+            name,
+            longname,
+            profile
+        }))
+    }
+
+    pub fn open(info: &DeviceInfo) -> Result<Device, DeviceError> {
+        let backend = AlsaBackend::open(&alsa::Card::new(info.card))?;
+        Device::from_backend(Box::new(backend), info.profile, info.id.clone())
+    }
+
+    pub fn simulated(profile: &'static DeviceProfile) -> Device {
+        Device::from_backend(Box::new(SimulatedBackend::new(profile)), profile, Device::simulated_id(profile))
+            .expect("simulated backends have every control in the profile")
+    }
+
+    // stand-in for a device that can't be used right now, so the app can carry on with its state
+    pub fn offline(profile: &'static DeviceProfile, id: String) -> Device {
+        Device::from_backend(Box::new(SimulatedBackend::offline(profile)), profile, id)
+            .expect("simulated backends have every control in the profile")
+    }
+
+    pub fn simulated_id(profile: &DeviceProfile) -> String {
+        format!("simulated {}", profile.name)
+    }
+
+    pub fn from_backend(backend: Box<dyn MixerBackend>, profile: &'static DeviceProfile, id: String) -> Result<Device, DeviceError> {
+        let controls = backend.controls();
+        let mut enums = HashMap::new();
+        for k in &controls {
+            if let ElemValue::Enum(_) = backend.get_value(k)? {
+                enums.insert(k.clone(), backend.enum_items(k)?);
+            }
+        }
+        let mixer_destinations: Vec<String> = (0..profile.mix_buses).map(|m| profile.mix_bus(m)).collect();
+
+        let p = profile;
+        let mut roles = HashMap::new();
+        for i in 0..p.capture_channels {
+            roles.insert(p.capture(i), Control::Capture(i));
+        }
+        for n in 0..p.matrix_inputs {
+            roles.insert(p.matrix_input(n), Control::MatrixInput(n));
+            for (m, mix) in mixer_destinations.iter().enumerate() {
+                roles.insert(p.matrix_gain(n, mix), Control::MatrixGain(n, m));
+            }
+        }
+        if let Some(global) = p.global() {
+            roles.insert(global, Control::Global);
+        }
+        for i in 0..p.outputs.len() {
+            for gain in p.output_gains(i) {
+                roles.insert(gain, Control::OutputGain(i));
+            }
+            roles.insert(p.output_source(i, false), Control::OutputSource(i));
+            roles.insert(p.output_source(i, true), Control::OutputSource(i));
+        }
+        for (i, n) in p.hi_z.iter().enumerate() {
+            roles.insert(p.hi_z(*n), Control::HiZ(i));
+        }
+        for (i, n) in p.pad.iter().enumerate() {
+            roles.insert(p.pad(*n), Control::Pad(i));
+        }
+        for (i, n) in p.air.iter().enumerate() {
+            roles.insert(p.air(*n), Control::Air(i));
+        }
+
+        let mut d = Device {
+            capture_sources: enums.get(&p.capture(0)).cloned().unwrap_or_default(),
+            // "Off" isn't a real source, it's handled separately where it's allowed
+            audio_sources: enums.get(&p.output_source(0, false)).into_iter().flatten()
+                .filter(|s| *s != "Off").cloned().collect(),
+            mixer_destinations,
+            controls: controls.iter().cloned().collect(),
+            enums,
+            roles,
+            writer: WriteQueue::new(backend.writer()?),
+            backend,
+            profile,
+            id,
+            known: DeviceState::new(),
+            written: DeviceState::new(),
+            changed: HashSet::new()
+        };
+        d.known = DeviceState::try_from(&d)?;
+        d.written = d.known.clone();
+        Ok(d)
+    }
+
+    // reads come from alsa-lib's cache, so these don't wait on the hardware
+    fn backend(&self) -> &dyn MixerBackend {
+        &*self.backend
+    }
+
+    // name of the card (or simulation) the device is talking to
+    pub fn describe(&self) -> String {
+        self.backend().describe()
+    }
+
+    pub fn pcm(&self) -> Option<String> {
+        self.backend().pcm()
+    }
+
+    pub fn is_hardware(&self) -> bool {
+        self.backend().is_hardware()
+    }
+
+    // every control's value as of the last time it was read
+    pub fn known(&self) -> &DeviceState {
+        &self.known
+    }
+
+    // names of every control, sorted
+    pub fn controls(&self) -> Vec<&String> {
+        let mut controls: Vec<&String> = self.controls.iter().collect();
+        controls.sort();
+        controls
+    }
+
+    // labels of the items of an enumerated control
+    pub fn enum_items(&self, control: &str) -> Option<&[String]> {
+        self.enums.get(control).map(Vec::as_slice)
+    }
+
+    // index of `label` in the enumerated control `control`
+    pub fn enum_item(&self, control: &str, label: &str) -> Option<EnumIndex> {
+        self.enums.get(control)?.iter().position(|i| i == label)
+    }
+
+    // value for an on/off control, which is an enum on some interfaces and a plain switch on others
+    fn switch_value(&self, control: &str, on: bool, on_label: &str) -> ElemValue {
+        match self.enums.get(control) {
+            Some(items) => ElemValue::Enum(items.iter().position(|i| (i == on_label) == on).unwrap_or(on as EnumIndex)),
+            None => ElemValue::Switch(on)
+        }
+    }
+
+    // index of an audio source (or off) in `control`, falling back to off if it can't be routed there
+    fn source_item(&self, control: &str, source: Option<&Port>) -> EnumIndex {
+        let label = source.map_or("Off", |s| s.0.as_str());
+        self.enum_item(control, label)
+            .or_else(|| self.enum_item(control, "Off"))
+            .unwrap_or(0)
+    }
+
+    // call `notify` from a background thread whenever something changes a control, or writes finish
+    pub fn watch(&self, notify: impl Fn() + Send + Clone + 'static) {
+        self.backend().watch(Box::new(notify.clone()));
+        self.writer.watch(Box::new(notify));
+    }
+
+    // pick up what finished writes left controls set to, returning the first failure
+    fn take_results(&mut self) -> Result<(), DeviceError> {
+        let mut result = Ok(());
+        for (k, r) in self.writer.results() {
+            match r {
+                Ok(v) => {
+                    self.known.insert(k, v);
+                }
+                Err(e) => if result.is_ok() || e.is_fatal() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    // pick up controls changed by something else (alsamixer, the driver) since the last call,
+    // and any writes that failed
+    pub fn poll_changes(&mut self) -> Result<(), DeviceError> {
+        let written = self.take_results();
+        if !self.backend.handle_events()? {
+            return written;
+        }
+        let now = DeviceState::try_from(&*self)?;
+        // our own writes are already in `known`, or were what was asked for if they haven't come back yet
+        for k in self.known.diff(&now).changed {
+            if self.written.get(&k).is_some_and(|v| same_value(v, &now[&k])) {
+                continue;
+            }
+            // it's set to this now, so there's nothing to write back once the app state catches up
+            self.written.insert(k.clone(), now[&k].clone());
+            self.changed.insert(k);
+        }
+        self.known = now;
+        written
+    }
+
+    // apply changes picked up by `poll_changes` to `state`, returning whether there were any
+    pub fn sync(&mut self, state: &mut AppState) -> bool {
+        if self.changed.is_empty() {
+            return false;
+        }
+        let changed = std::mem::take(&mut self.changed);
+        let hw = self.known.to_state(self);
+        let layout = matrix_layout(state, self.profile.matrix_inputs);
+        // set when a matrix change can't be explained in terms of the existing entries
+        let mut rebuild = false;
+
+        for k in &changed {
+            let Some(role) = self.roles.get(k) else {
+                continue;
+            };
+            match *role {
+                Control::Capture(i) => if let (Some(c), Some(v)) = (state.capture.get_mut(i), hw.capture.get(i)) {
+                    *c = v.clone();
+                },
+                Control::Global => {
+                    state.global_gain = hw.global_gain;
+                    state.global_mute = hw.global_mute;
+                }
+                Control::HiZ(i) => copy_at(&mut state.hi_z, &hw.hi_z, i),
+                Control::Pad(i) => copy_at(&mut state.pad, &hw.pad, i),
+                Control::Air(i) => copy_at(&mut state.air, &hw.air, i),
+                Control::OutputGain(i) => if let (Some(o), Some(h)) = (state.outputs.get_mut(i), hw.outputs.get(i)) {
+                    o.gain = h.gain;
+                    o.mute = h.mute;
+                },
+                Control::OutputSource(i) => if let (Some(o), Some(h)) = (state.outputs.get_mut(i), hw.outputs.get(i)) {
+                    o.source = h.source.clone();
+                    o.split = h.split;
+                },
+                Control::MatrixInput(n) | Control::MatrixGain(n, _) => {
+                    // the entry on this matrix input, and which of its channels it is
+                    let owner = layout.iter().find_map(|(i, input)| {
+                        let channels = if state.mixer_entries[*i].stereo { 2 } else { 1 };
+                        (n >= *input && n < input + channels).then_some((*i, n - input))
+                    });
+                    rebuild |= !self.apply_matrix_change(state, k, *role, owner);
+                }
+            }
+        }
+
+        if rebuild {
+            let mut entries = hw.mixer_entries;
+            // entries that are still playing the same thing keep their names, and their ids (and their
+            // destinations' ids) so midi mappings stay on them
+            let mut old: Vec<&MixerEntry> = state.mixer_entries.iter().collect();
+            for e in &mut entries {
+                let Some(k) = old.iter().position(|o|
+                    o.stereo == e.stereo && o.source == e.source && (!e.stereo || o.source_r == e.source_r)
+                ) else {
+                    continue;
+                };
+                let o = old.remove(k);
+                e.name = o.name.clone();
+                e.id = o.id;
+                for d in &mut e.dests {
+                    if let Some(od) = o.dests.iter().find(|od| od.stereo == d.stereo && od.dest == d.dest && (!d.stereo || od.dest_r == d.dest_r)) {
+                        d.id = od.id;
+                    }
+                }
+            }
+            state.mixer_entries = entries;
+        }
+        true
+    }
+
+    // reflect a changed matrix control in the entry on its input, returning false if that isn't possible
+    fn apply_matrix_change(&self, state: &mut AppState, control: &str, role: Control, owner: Option<(usize, usize)>) -> bool {
+        let gain = match self.known.get(control) {
+            Some(ElemValue::Knob { db, .. }) => *db,
+            _ => GAIN_MIN
+        };
+        match (owner, role) {
+            (Some((i, c)), Control::MatrixInput(_)) => {
+                let source = match self.known.get(control) {
+                    Some(ElemValue::Enum(item)) => self.enums.get(control).and_then(|items| items.get(*item))
+                        .filter(|l| self.audio_sources.contains(l)),
+                    _ => None
+                };
+                // switched off
+                let Some(source) = source.map(|s| Port::new(s)) else {
+                    return false;
+                };
+                let e = &mut state.mixer_entries[i];
+                if c == 0 { e.source = source } else { e.source_r = source }
+                if e.stereo {
+                    e.split = !is_port_pair(&e.source, &e.source_r, &self.audio_sources);
+                }
+                true
+            }
+            (Some((i, c)), Control::MatrixGain(_, m)) => {
+                let law = state.pan_law;
+                let mix = Port::at(&self.mixer_destinations, m);
+                let heard: Vec<bool> = {
+                    let soloed = soloed_mixes(state, self.profile.matrix_inputs);
+                    let e = &state.mixer_entries[i];
+                    e.dests.iter().map(|d| audible(e, d, &mix, &soloed)).collect()
+                };
+                let e = &mut state.mixer_entries[i];
+                let stereo = e.stereo;
+                let mut found = false;
+                let mut unmute = false;
+                for (d, heard) in e.dests.iter_mut().zip(heard) {
+                    let offset = routes(stereo, d).into_iter()
+                        .zip(d.pan_offsets(stereo, law))
+                        .find(|((rc, rm), _)| (*rc, *rm) == (c, &mix))
+                        .map(|(_, offset)| offset);
+                    let Some(offset) = offset else {
+                        continue;
+                    };
+                    found = true;
+                    // panned all the way to the other side, so turning it up there needs the entries rebuilding
+                    if offset == f32::NEG_INFINITY {
+                        if gain > GAIN_MIN {
+                            return false;
+                        }
+                        continue;
+                    }
+                    // see `audible` for what muting and soloing mean here
+                    if gain > GAIN_MIN || heard {
+                        d.gain = (gain - offset).max(GAIN_MIN);
+                        d.mute = false;
+                        unmute |= gain > GAIN_MIN;
+                    }
+                }
+                if unmute {
+                    e.mute = false;
+                }
+                found || gain <= GAIN_MIN
+            }
+            // an input no entry is using, which only matters once it's audible
+            (None, Control::MatrixInput(n) | Control::MatrixGain(n, _)) => !self.input_audible(n),
+            _ => true
+        }
+    }
+
+    fn input_audible(&self, n: usize) -> bool {
+        let control = self.profile.matrix_input(n);
+        let routed = match self.known.get(&control) {
+            Some(ElemValue::Enum(i)) => Some(*i) != self.enum_item(&control, "Off"),
+            _ => false
+        };
+        routed && self.mixer_destinations.iter().any(|mix| matches!(
+            self.known.get(&self.profile.matrix_gain(n, mix)),
+            Some(ElemValue::Knob { db, .. }) if *db > GAIN_MIN
+        ))
+    }
+
+    // queue a write to one control, whether or not the app state has anything to do with it
+    pub fn set(&mut self, control: &str, value: ElemValue) -> Result<(), DeviceError> {
+        if !self.controls.contains(control) {
+            return Err(DeviceError::ControlMissing(control.to_owned()));
+        }
+        self.writer.push(control.to_owned(), value.clone());
+        self.written.insert(control.to_owned(), value);
+        Ok(())
+    }
+
+    // wait for every queued write, returning the first that failed
+    pub fn finish(mut self) -> Result<(), DeviceError> {
+        self.writer.flush();
+        self.take_results()
+    }
+
+    // queue writes for the controls `state` changed, returning the first write that's failed since last time
+    pub fn update(&mut self, state: &AppState) -> Result<(), DeviceError> {
+        let new = DeviceState::from_state(state, self);
+        // compared with what was asked for rather than read back, so rounded gains aren't written every frame
+        let diff = self.written.diff(&new);
+        for k in diff.changed.into_iter().chain(diff.added) {
+            // don't undo changes from elsewhere before they've been synced
+            if self.changed.contains(&k) {
+                continue;
+            }
+            let value = new[&k].clone();
+            self.writer.push(k.clone(), value.clone());
+            // not retried if it fails, it'll only fail again
+            self.written.insert(k, value);
+        }
+        self.take_results()
+    }
 }
 
-impl Device {
-    pub fn new() -> Option<Device> {
-        let c = alsa::card::Iter::new().find_map(|r| {
-            let c = r.unwrap();
-            if c.get_name().unwrap() == CARD_NAME { Some(c) } else { None }
-        })?;
-        // let ctl = alsa::Ctl::from_card(&c, false).unwrap();
-        let mixer = Box::<alsa::Mixer>::leak(Box::from(
-            alsa::Mixer::new(format!("hw:{}", c.get_name().unwrap()).as_str(), false).unwrap()
-        ));
-        let selems: Selems = HashMap::from_iter(mixer.iter()
-            .flat_map(|e| Selem::new(e))
-            .map(|s| (s.get_id().get_name().unwrap().to_owned(), s)));
-
-        Some(Device {
-            capture_sources: get_enums(selems.get("Input Source 01").unwrap()),
-            audio_sources: get_enums(selems.get("TODO").unwrap()),
-            mixer_destinations: /*get_enums(selems.iter().find_map(|(k, v)|
-                    if k.starts_with("Master") && k.ends_with("Source") { Some(v) } else { None }
-                ).unwrap())*/
-                selems.iter().filter_map(|(k, _v)|
-                    if k.starts_with("Matrix 01 Mix ") { Some(k.strip_prefix("Matrix 01 ").unwrap().to_owned()) } else { None }).collect(),
-            selems,
-        })
-    }
-
-    pub fn update(&mut self, app: &ScarlettControlApp) {
-        let old = DeviceState::from(&*self);
-        let new = DeviceState::from(app);
-        for k in old.diff(&new) {
-            self.selems.get(&k).unwrap().set_value(new.get(&k).unwrap());
-        }
-    }
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::AtomicUsize, Mutex};
+
+    use super::*;
+
+    fn state(values: &[(&str, ElemValue)]) -> DeviceState {
+        DeviceState(values.iter().map(|(k, v)| (k.to_string(), v.clone())).collect())
+    }
+
+    fn keys(k: &[&str]) -> Vec<String> {
+        k.iter().map(|k| k.to_string()).collect()
+    }
+
+    #[test]
+    fn diff_of_identical_states_is_empty() {
+        let a = state(&[("A", ElemValue::Enum(1)), ("B", ElemValue::Switch(true))]);
+        assert!(a.diff(&a.clone()).is_empty());
+    }
+
+    #[test]
+    fn diff_reports_changed_added_and_removed() {
+        let old = state(&[
+            ("Same", ElemValue::Enum(1)),
+            ("Gain", ElemValue::Knob { db: -10.0, muted: false }),
+            ("Mute", ElemValue::Knob { db: 0.0, muted: false }),
+            ("Gone", ElemValue::Switch(false))
+        ]);
+        let new = state(&[
+            ("Same", ElemValue::Enum(1)),
+            ("Gain", ElemValue::Knob { db: -12.0, muted: false }),
+            ("Mute", ElemValue::Knob { db: 0.0, muted: true }),
+            ("New", ElemValue::Switch(true))
+        ]);
+        assert_eq!(old.diff(&new), StateDiff {
+            changed: keys(&["Gain", "Mute"]),
+            added: keys(&["New"]),
+            removed: keys(&["Gone"])
+        });
+        let back = new.diff(&old);
+        assert_eq!(back.added, keys(&["Gone"]));
+        assert_eq!(back.removed, keys(&["New"]));
+    }
+
+    // counts the writes that reach the simulated controls
+    struct Counting(Arc<Mutex<SimulatedBackend>>, Arc<AtomicUsize>);
+
+    impl MixerBackend for Counting {
+        fn controls(&self) -> Vec<String> {
+            self.0.lock().unwrap().controls()
+        }
+
+        fn get_value(&self, control: &str) -> Result<ElemValue, DeviceError> {
+            self.0.lock().unwrap().get_value(control)
+        }
+
+        fn set_value(&mut self, control: &str, value: &ElemValue) -> Result<(), DeviceError> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.lock().unwrap().set_value(control, value)
+        }
+
+        fn enum_items(&self, control: &str) -> Result<Vec<String>, DeviceError> {
+            self.0.lock().unwrap().enum_items(control)
+        }
+
+        fn describe(&self) -> String {
+            self.0.lock().unwrap().describe()
+        }
+
+        fn writer(&self) -> Result<Box<dyn MixerBackend>, DeviceError> {
+            Ok(Box::new(Counting(self.0.clone(), self.1.clone())))
+        }
+    }
+
+    fn counting(sim: &Arc<Mutex<SimulatedBackend>>, writes: &Arc<AtomicUsize>) -> Device {
+        let profile = DeviceProfile::default_profile();
+        Device::from_backend(Box::new(Counting(sim.clone(), writes.clone())), profile, "test".to_owned()).unwrap()
+    }
+
+    #[test]
+    fn update_only_writes_changed_controls() {
+        let sim = Arc::new(Mutex::new(SimulatedBackend::new(DeviceProfile::default_profile())));
+        let writes = Arc::new(AtomicUsize::new(0));
+
+        // settle the controls on what the app state maps them to
+        let mut device = counting(&sim, &writes);
+        let mut state = device.known().to_state(&device);
+        device.update(&state).unwrap();
+        drop(device);
+
+        writes.store(0, Ordering::Relaxed);
+        let mut device = counting(&sim, &writes);
+        device.update(&state).unwrap();
+        device.update(&state).unwrap();
+        state.global_gain = -20.0;
+        device.update(&state).unwrap();
+        device.update(&state).unwrap();
+        // waits for the writes to finish
+        drop(device);
+        assert_eq!(writes.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn panned_sources_are_louder_on_their_side() {
+        let device = Device::simulated(DeviceProfile::default_profile());
+        let mut state = AppState::default();
+        let mut entry = MixerEntry::new(&device);
+        entry.dests[0].stereo = true;
+        entry.dests[0].gain = -10.0;
+        entry.dests[0].pan = -1.0;
+        state.mixer_entries.push(entry);
+        state.pan_law = PanLaw::Minus6;
+
+        let d = DeviceState::from_state(&state, &device);
+        let p = device.profile;
+        let gain = |mix: usize| match d.get(&p.matrix_gain(0, &device.mixer_destinations[mix])) {
+            Some(ElemValue::Knob { db, .. }) => *db,
+            _ => panic!("no gain into mix {mix}")
+        };
+        assert_eq!(gain(0), -4.0);
+        assert_eq!(gain(1), GAIN_MIN);
+    }
+
+    #[test]
+    fn solos_only_silence_their_own_mixes() {
+        let device = Device::simulated(DeviceProfile::default_profile());
+        let mixes = &device.mixer_destinations;
+        let mut state = AppState::default();
+        for _ in 0..2 {
+            let mut entry = MixerEntry::new(&device);
+            entry.dests[0].dest = Port::at(mixes, 0);
+            entry.add_dest(&device);
+            entry.dests[1].dest = Port::at(mixes, 2);
+            state.mixer_entries.push(entry);
+        }
+        state.mixer_entries[0].dests[0].solo = true;
+
+        let gain = |state: &AppState, input: usize, mix: usize| {
+            match DeviceState::from_state(state, &device).get(&device.profile.matrix_gain(input, &mixes[mix])) {
+                Some(ElemValue::Knob { db, .. }) => *db,
+                _ => panic!("no gain into mix {mix}")
+            }
+        };
+        assert_eq!(gain(&state, 0, 0), 0.0);
+        assert_eq!(gain(&state, 1, 0), GAIN_MIN);
+        assert_eq!(gain(&state, 1, 2), 0.0);
+
+        // a muted entry can't be heard even when it's soloed
+        state.mixer_entries[0].mute = true;
+        state.mixer_entries[0].solo = true;
+        assert_eq!(gain(&state, 0, 0), GAIN_MIN);
+        assert_eq!(gain(&state, 0, 2), GAIN_MIN);
+        // but it still silences everything else in its mixes
+        assert_eq!(gain(&state, 1, 2), GAIN_MIN);
+    }
+}
@@ -0,0 +1,139 @@
+// a vertical fader for gains, scaled like a mixing desk so the useful range around 0dB gets most of the travel.
+// drag to move it (with shift for fine adjustment), scroll to nudge it and double-click to go back to 0dB
+
+use egui::{pos2, vec2, Align2, FontId, Rect, Sense, Stroke};
+
+use crate::{device::GAIN_MIN, theme};
+
+pub const GAIN_MAX: f32 = 6.0;
+
+// gains and where they are along the fader, from the bottom. in between is linear in dB
+const SCALE: [(f32, f32); 9] = [
+    (GAIN_MIN, 0.0),
+    (-60.0, 0.05),
+    (-40.0, 0.15),
+    (-30.0, 0.25),
+    (-20.0, 0.4),
+    (-10.0, 0.6),
+    (-5.0, 0.7),
+    (0.0, 0.8),
+    (GAIN_MAX, 1.0)
+];
+
+const WIDTH: f32 = 48.0;
+const HEIGHT: f32 = 180.0;
+const TRACK_WIDTH: f32 = 4.0;
+const THUMB: egui::Vec2 = vec2(22.0, 10.0);
+// dB for each notch of the scroll wheel
+const NUDGE: f32 = 0.5;
+// how much slower things move with shift held
+const FINE: f32 = 0.1;
+
+// where `db` is along the fader, from 0 at the bottom to 1 at the top
+pub fn position(db: f32) -> f32 {
+    let db = db.clamp(GAIN_MIN, GAIN_MAX);
+    SCALE.windows(2)
+        .find(|w| db <= w[1].0)
+        .map_or(1.0, |w| {
+            let ((d0, p0), (d1, p1)) = (w[0], w[1]);
+            p0 + (db - d0) / (d1 - d0) * (p1 - p0)
+        })
+}
+
+// the gain at `position` along the fader
+pub fn gain(position: f32) -> f32 {
+    let position = position.clamp(0.0, 1.0);
+    SCALE.windows(2)
+        .find(|w| position <= w[1].1)
+        .map_or(GAIN_MAX, |w| {
+            let ((d0, p0), (d1, p1)) = (w[0], w[1]);
+            d0 + (position - p0) / (p1 - p0) * (d1 - d0)
+        })
+}
+
+pub struct Fader<'a> {
+    db: &'a mut f32,
+    muted: bool
+}
+
+impl<'a> Fader<'a> {
+    pub fn new(db: &'a mut f32) -> Self {
+        Fader { db, muted: false }
+    }
+
+    // drawn greyed out, but can still be moved
+    pub fn muted(mut self, muted: bool) -> Self {
+        self.muted = muted;
+        self
+    }
+}
+
+impl egui::Widget for Fader<'_> {
+    fn ui(self, ui: &mut egui::Ui) -> egui::Response {
+        let (rect, mut response) = ui.allocate_exact_size(vec2(WIDTH, HEIGHT), Sense::click_and_drag());
+        // the thumb's centre travels between these
+        let track = Rect::from_center_size(
+            pos2(rect.left() + WIDTH * 0.65, rect.center().y),
+            vec2(TRACK_WIDTH, HEIGHT - THUMB.y)
+        );
+        let fine = ui.input(|i| i.modifiers.shift);
+        let old = *self.db;
+
+        if response.double_clicked() {
+            *self.db = 0.0;
+        } else if response.dragged() {
+            let speed = if fine { FINE } else { 1.0 };
+            let moved = -response.drag_delta().y / track.height() * speed;
+            *self.db = gain(position(*self.db) + moved);
+        }
+        if response.hovered() {
+            // taken so whatever it's in doesn't scroll too
+            let scroll = ui.input_mut(|i| {
+                let y = i.raw_scroll_delta.y;
+                i.raw_scroll_delta = egui::Vec2::ZERO;
+                i.smooth_scroll_delta = egui::Vec2::ZERO;
+                y
+            });
+            if scroll != 0.0 {
+                let step = if fine { NUDGE * FINE } else { NUDGE };
+                *self.db = (*self.db + step * scroll.signum()).clamp(GAIN_MIN, GAIN_MAX);
+            }
+        }
+        if *self.db != old {
+            response.mark_changed();
+        }
+
+        let painter = ui.painter();
+        let y = |db: f32| track.bottom() - position(db) * track.height();
+        let text = ui.visuals().weak_text_color();
+        for (db, label) in [(GAIN_MAX, "+6"), (0.0, "0"), (-10.0, "10"), (-20.0, "20"), (-40.0, "40"), (GAIN_MIN, "∞")] {
+            let tick_y = y(db);
+            painter.line_segment([pos2(track.left() - 8.0, tick_y), pos2(track.left() - 3.0, tick_y)], Stroke::new(1.0, text));
+            painter.text(pos2(track.left() - 10.0, tick_y), Align2::RIGHT_CENTER, label, FontId::proportional(9.0), text);
+        }
+        painter.rect_filled(track, 2.0, theme::colors::BG2);
+        let fill = Rect::from_min_max(pos2(track.left(), y(*self.db)), track.max);
+        painter.rect_filled(fill, 2.0, if self.muted { theme::colors::TEXT_DISABLED } else { theme::colors::ACTIVE });
+        let thumb = Rect::from_center_size(pos2(track.center().x, y(*self.db)), THUMB);
+        let thumb_color = if response.hovered() || response.dragged() { theme::colors::ON } else { theme::colors::TEXT };
+        painter.rect_filled(thumb, 2.0, if self.muted { theme::colors::TEXT_DISABLED } else { thumb_color });
+        painter.line_segment([pos2(thumb.left() + 3.0, thumb.center().y), pos2(thumb.right() - 3.0, thumb.center().y)], Stroke::new(1.0, theme::colors::FRAME));
+
+        response.on_hover_text(format!("{:+.1}dB\nShift for fine adjustment, double-click for 0dB", *self.db))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_and_gains_agree() {
+        for db in [GAIN_MIN, -90.0, -60.0, -33.0, -12.5, -1.0, 0.0, 3.0, GAIN_MAX] {
+            assert!((gain(position(db)) - db).abs() < 0.01, "{}", db);
+        }
+        assert_eq!(position(0.0), 0.8);
+        assert_eq!(position(100.0), 1.0);
+        assert_eq!(gain(-1.0), GAIN_MIN);
+    }
+}
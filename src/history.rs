@@ -0,0 +1,421 @@
+// undo and redo. each step is the edits the ui made, as which setting changed and what it was before
+// and after, or which entry or destination was added or removed. undoing one puts back only those, so
+// anything changed from elsewhere since (the hardware, osc, midi or d-bus) stays as it is
+use crate::{app::AppState, state::{MixerDestination, MixerEntry, PanLaw, Port}};
+
+// oldest steps are forgotten after this many
+const LIMIT: usize = 100;
+
+// a setting, by where it is in the state. sides are 0 for left and 1 for right
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Field {
+    Capture(usize),
+    GlobalGain,
+    GlobalMute,
+    HiZ(usize),
+    Pad(usize),
+    Air(usize),
+    PanLaw,
+    OutputName(usize),
+    OutputGain(usize),
+    OutputMute(usize),
+    OutputSource(usize, usize),
+    OutputSplit(usize),
+    EntryName(usize),
+    EntryEnabled(usize),
+    EntryMute(usize),
+    EntrySolo(usize),
+    EntryStereo(usize),
+    EntrySplit(usize),
+    EntrySource(usize, usize),
+    DestStereo(usize, usize),
+    DestPort(usize, usize, usize),
+    DestSplit(usize, usize),
+    DestGain(usize, usize),
+    DestMute(usize, usize),
+    DestPan(usize, usize),
+    DestSolo(usize, usize)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Value {
+    Number(f32),
+    Switch(bool),
+    Port(Port),
+    Capture(Option<Port>),
+    Text(String),
+    PanLaw(PanLaw)
+}
+
+enum Slot<'a> {
+    Number(&'a mut f32),
+    Switch(&'a mut bool),
+    Port(&'a mut Port),
+    Capture(&'a mut Option<Port>),
+    Text(&'a mut String),
+    PanLaw(&'a mut PanLaw)
+}
+
+impl Slot<'_> {
+    fn get(&self) -> Value {
+        match self {
+            Slot::Number(n) => Value::Number(**n),
+            Slot::Switch(on) => Value::Switch(**on),
+            Slot::Port(p) => Value::Port((*p).clone()),
+            Slot::Capture(p) => Value::Capture((*p).clone()),
+            Slot::Text(t) => Value::Text((*t).clone()),
+            Slot::PanLaw(law) => Value::PanLaw(**law)
+        }
+    }
+
+    fn set(self, value: Value) {
+        match (self, value) {
+            (Slot::Number(n), Value::Number(v)) => *n = v,
+            (Slot::Switch(on), Value::Switch(v)) => *on = v,
+            (Slot::Port(p), Value::Port(v)) => *p = v,
+            (Slot::Capture(p), Value::Capture(v)) => *p = v,
+            (Slot::Text(t), Value::Text(v)) => *t = v,
+            (Slot::PanLaw(law), Value::PanLaw(v)) => *law = v,
+            _ => {}
+        }
+    }
+}
+
+impl Field {
+    // where the setting is in `s`, if it's still there
+    fn slot(self, s: &mut AppState) -> Option<Slot<'_>> {
+        let side = |side: usize, l, r| if side == 0 { l } else { r };
+        Some(match self {
+            Field::Capture(k) => Slot::Capture(s.capture.get_mut(k)?),
+            Field::GlobalGain => Slot::Number(&mut s.global_gain),
+            Field::GlobalMute => Slot::Switch(&mut s.global_mute),
+            Field::HiZ(k) => Slot::Switch(s.hi_z.get_mut(k)?),
+            Field::Pad(k) => Slot::Switch(s.pad.get_mut(k)?),
+            Field::Air(k) => Slot::Switch(s.air.get_mut(k)?),
+            Field::PanLaw => Slot::PanLaw(&mut s.pan_law),
+            Field::OutputName(k) => Slot::Text(&mut s.outputs.get_mut(k)?.name),
+            Field::OutputGain(k) => Slot::Number(&mut s.outputs.get_mut(k)?.gain),
+            Field::OutputMute(k) => Slot::Switch(&mut s.outputs.get_mut(k)?.mute),
+            Field::OutputSource(k, c) => {
+                let o = s.outputs.get_mut(k)?;
+                Slot::Port(side(c, &mut o.source.0, &mut o.source.1))
+            }
+            Field::OutputSplit(k) => Slot::Switch(&mut s.outputs.get_mut(k)?.split),
+            Field::EntryName(i) => Slot::Text(&mut s.mixer_entries.get_mut(i)?.name),
+            Field::EntryEnabled(i) => Slot::Switch(&mut s.mixer_entries.get_mut(i)?.enabled),
+            Field::EntryMute(i) => Slot::Switch(&mut s.mixer_entries.get_mut(i)?.mute),
+            Field::EntrySolo(i) => Slot::Switch(&mut s.mixer_entries.get_mut(i)?.solo),
+            Field::EntryStereo(i) => Slot::Switch(&mut s.mixer_entries.get_mut(i)?.stereo),
+            Field::EntrySplit(i) => Slot::Switch(&mut s.mixer_entries.get_mut(i)?.split),
+            Field::EntrySource(i, c) => {
+                let e = s.mixer_entries.get_mut(i)?;
+                Slot::Port(side(c, &mut e.source, &mut e.source_r))
+            }
+            Field::DestStereo(i, j) => Slot::Switch(&mut dest(s, i, j)?.stereo),
+            Field::DestPort(i, j, c) => {
+                let d = dest(s, i, j)?;
+                Slot::Port(side(c, &mut d.dest, &mut d.dest_r))
+            }
+            Field::DestSplit(i, j) => Slot::Switch(&mut dest(s, i, j)?.split),
+            Field::DestGain(i, j) => Slot::Number(&mut dest(s, i, j)?.gain),
+            Field::DestMute(i, j) => Slot::Switch(&mut dest(s, i, j)?.mute),
+            Field::DestPan(i, j) => Slot::Number(&mut dest(s, i, j)?.pan),
+            Field::DestSolo(i, j) => Slot::Switch(&mut dest(s, i, j)?.solo)
+        })
+    }
+}
+
+fn dest(s: &mut AppState, i: usize, j: usize) -> Option<&mut MixerDestination> {
+    s.mixer_entries.get_mut(i)?.dests.get_mut(j)
+}
+
+// every setting in `s`
+fn fields(s: &AppState) -> Vec<Field> {
+    let mut fields = vec![Field::GlobalGain, Field::GlobalMute, Field::PanLaw];
+    fields.extend((0..s.capture.len()).map(Field::Capture));
+    fields.extend((0..s.hi_z.len()).map(Field::HiZ));
+    fields.extend((0..s.pad.len()).map(Field::Pad));
+    fields.extend((0..s.air.len()).map(Field::Air));
+    for k in 0..s.outputs.len() {
+        fields.extend([Field::OutputName(k), Field::OutputGain(k), Field::OutputMute(k), Field::OutputSource(k, 0),
+            Field::OutputSource(k, 1), Field::OutputSplit(k)]);
+    }
+    for (i, e) in s.mixer_entries.iter().enumerate() {
+        fields.extend([Field::EntryName(i), Field::EntryEnabled(i), Field::EntryMute(i), Field::EntrySolo(i),
+            Field::EntryStereo(i), Field::EntrySplit(i), Field::EntrySource(i, 0), Field::EntrySource(i, 1)]);
+        for j in 0..e.dests.len() {
+            fields.extend([Field::DestStereo(i, j), Field::DestPort(i, j, 0), Field::DestPort(i, j, 1), Field::DestSplit(i, j),
+                Field::DestGain(i, j), Field::DestMute(i, j), Field::DestPan(i, j), Field::DestSolo(i, j)]);
+        }
+    }
+    fields
+}
+
+#[derive(Clone, PartialEq)]
+enum Edit {
+    // a setting, from one value to another
+    Set(Field, Value, Value),
+    // an entry added (from none) or removed (to none) at an index
+    Entry(usize, Option<MixerEntry>, Option<MixerEntry>),
+    // a destination added to or removed from an entry
+    Dest(usize, usize, Option<MixerDestination>, Option<MixerDestination>)
+}
+
+impl Edit {
+    fn inverse(&self) -> Edit {
+        match self.clone() {
+            Edit::Set(field, old, new) => Edit::Set(field, new, old),
+            Edit::Entry(i, old, new) => Edit::Entry(i, new, old),
+            Edit::Dest(i, j, old, new) => Edit::Dest(i, j, new, old)
+        }
+    }
+
+    // make the edit to `s`, as far as it still can be
+    fn apply(&self, s: &mut AppState) {
+        match self {
+            Edit::Set(field, _, new) => {
+                if let Some(slot) = field.slot(s) {
+                    slot.set(new.clone());
+                }
+            }
+            Edit::Entry(i, _, Some(e)) => s.mixer_entries.insert((*i).min(s.mixer_entries.len()), e.clone()),
+            Edit::Entry(i, _, None) => {
+                if *i < s.mixer_entries.len() {
+                    s.mixer_entries.remove(*i);
+                }
+            }
+            Edit::Dest(i, j, _, new) => {
+                let Some(e) = s.mixer_entries.get_mut(*i) else {
+                    return;
+                };
+                match new {
+                    Some(d) => e.dests.insert((*j).min(e.dests.len()), d.clone()),
+                    None if *j < e.dests.len() => {
+                        e.dests.remove(*j);
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
+// where `a` and `b` first differ, or the end of the shorter one
+fn first_difference<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    a.iter().zip(b).position(|(x, y)| x != y).unwrap_or(a.len().min(b.len()))
+}
+
+// the edits that turn `before` into `after`, in the order they're made
+fn diff(before: &AppState, after: &AppState) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    // entries and destinations added or removed first, so what's left can be compared setting by setting
+    let mut s = before.clone();
+    while s.mixer_entries.len() > after.mixer_entries.len() {
+        let i = first_difference(&s.mixer_entries, &after.mixer_entries);
+        edits.push(Edit::Entry(i, Some(s.mixer_entries.remove(i)), None));
+    }
+    while s.mixer_entries.len() < after.mixer_entries.len() {
+        let i = first_difference(&s.mixer_entries, &after.mixer_entries);
+        let e = after.mixer_entries[i].clone();
+        s.mixer_entries.insert(i, e.clone());
+        edits.push(Edit::Entry(i, None, Some(e)));
+    }
+    for (i, (e, after)) in s.mixer_entries.iter_mut().zip(&after.mixer_entries).enumerate() {
+        while e.dests.len() > after.dests.len() {
+            let j = first_difference(&e.dests, &after.dests);
+            edits.push(Edit::Dest(i, j, Some(e.dests.remove(j)), None));
+        }
+        while e.dests.len() < after.dests.len() {
+            let j = first_difference(&e.dests, &after.dests);
+            let d = after.dests[j].clone();
+            e.dests.insert(j, d.clone());
+            edits.push(Edit::Dest(i, j, None, Some(d)));
+        }
+    }
+
+    let mut after = after.clone();
+    for field in fields(&after) {
+        let old = field.slot(&mut s).map(|slot| slot.get());
+        let new = field.slot(&mut after).map(|slot| slot.get());
+        if let (Some(old), Some(new)) = (old, new) {
+            if old != new {
+                edits.push(Edit::Set(field, old, new));
+            }
+        }
+    }
+    edits
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    // the edits of a step that's still going, eg. a gain being dragged or a name being typed
+    pending: Vec<Edit>
+}
+
+impl History {
+    // the ui changed `before` to `after`. while it's `busy`, more changes are part of the same step
+    pub fn record(&mut self, before: AppState, after: &AppState, busy: bool) {
+        if before != *after {
+            for edit in diff(&before, after) {
+                self.add(edit);
+            }
+        }
+        if !busy {
+            self.commit();
+        }
+    }
+
+    fn add(&mut self, edit: Edit) {
+        // a setting changed again since the last entry or destination was added or removed is still one edit
+        if let Edit::Set(field, _, new) = &edit {
+            let earlier = self.pending.iter_mut().rev()
+                .take_while(|e| matches!(e, Edit::Set(..)))
+                .find(|e| matches!(e, Edit::Set(f, ..) if f == field));
+            if let Some(Edit::Set(_, _, last)) = earlier {
+                *last = new.clone();
+                return;
+            }
+        }
+        self.pending.push(edit);
+    }
+
+    fn commit(&mut self) {
+        // dragging something back to where it started isn't an edit
+        self.pending.retain(|e| !matches!(e, Edit::Set(_, old, new) if old == new));
+        if !self.pending.is_empty() {
+            if self.undo.len() == LIMIT {
+                self.undo.remove(0);
+            }
+            self.undo.push(std::mem::take(&mut self.pending));
+            self.redo.clear();
+        }
+    }
+
+    pub fn undo(&mut self, state: &mut AppState) {
+        self.commit();
+        if let Some(step) = self.undo.pop() {
+            for edit in step.iter().rev() {
+                edit.inverse().apply(state);
+            }
+            self.redo.push(step);
+        }
+    }
+
+    pub fn redo(&mut self, state: &mut AppState) {
+        self.commit();
+        if let Some(step) = self.redo.pop() {
+            for edit in &step {
+                edit.apply(state);
+            }
+            self.undo.push(step);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || !self.pending.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        *self = History::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{device::Device, profile::DeviceProfile};
+
+    use super::*;
+
+    fn gain(db: f32) -> AppState {
+        AppState {
+            global_gain: db,
+            ..Default::default()
+        }
+    }
+
+    fn device() -> (Device, AppState) {
+        let device = Device::simulated(DeviceProfile::find("Scarlett 18i6").unwrap());
+        let mut state = device.known().to_state(&device);
+        state.mixer_entries = vec![MixerEntry::new(&device)];
+        (device, state)
+    }
+
+    // have the ui make `edit`
+    fn edit(history: &mut History, state: &mut AppState, edit: impl FnOnce(&mut AppState)) {
+        let before = state.clone();
+        edit(state);
+        history.record(before, state, false);
+    }
+
+    #[test]
+    fn drags_are_one_step() {
+        let mut history = History::default();
+        let mut state = gain(0.0);
+        for db in 1..10 {
+            let before = state.clone();
+            state.global_gain = db as f32;
+            history.record(before, &state, true);
+        }
+        history.record(state.clone(), &state, false);
+
+        history.undo(&mut state);
+        assert_eq!(state.global_gain, 0.0);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn redo_goes_back_until_something_else_changes() {
+        let mut history = History::default();
+        let mut state = gain(0.0);
+        for db in [-1.0, -2.0] {
+            edit(&mut history, &mut state, |s| s.global_gain = db);
+        }
+
+        history.undo(&mut state);
+        history.undo(&mut state);
+        history.redo(&mut state);
+        assert_eq!(state.global_gain, -1.0);
+
+        edit(&mut history, &mut state, |s| s.global_gain = -5.0);
+        assert!(!history.can_redo());
+        history.undo(&mut state);
+        assert_eq!(state.global_gain, -1.0);
+    }
+
+    #[test]
+    fn undo_leaves_changes_from_elsewhere() {
+        let (device, mut state) = device();
+        let mut history = History::default();
+        let (gain, dests) = (state.outputs[0].gain, state.mixer_entries[0].dests.len());
+        edit(&mut history, &mut state, |s| s.outputs[0].gain = -10.0);
+        edit(&mut history, &mut state, |s| {
+            s.mixer_entries[0].dests.remove(0);
+        });
+        edit(&mut history, &mut state, |s| s.mixer_entries.insert(0, MixerEntry::new(&device)));
+        // eg. from osc, or the hardware
+        state.outputs[0].mute = true;
+        state.global_gain = -5.0;
+        state.mixer_entries[1].name = "Guitar".to_owned();
+
+        history.undo(&mut state);
+        assert_eq!(state.mixer_entries.len(), 1);
+        assert_eq!(state.mixer_entries[0].name, "Guitar");
+        history.undo(&mut state);
+        assert_eq!(state.mixer_entries[0].dests.len(), dests);
+        history.undo(&mut state);
+        assert_eq!(state.outputs[0].gain, gain);
+        assert!(state.outputs[0].mute);
+        assert_eq!(state.global_gain, -5.0);
+
+        history.redo(&mut state);
+        history.redo(&mut state);
+        assert_eq!(state.mixer_entries[0].dests.len(), dests - 1);
+        assert_eq!((state.outputs[0].gain, state.mixer_entries[0].name.as_str()), (-10.0, "Guitar"));
+    }
+}
@@ -2,4 +2,18 @@ mod app;
 mod theme;
 mod state;
 mod device;
-pub use app::ScarlettControlApp;
\ No newline at end of file
+mod backend;
+mod profile;
+mod writer;
+mod session;
+mod schema;
+mod history;
+mod matrix;
+mod meter;
+mod fader;
+mod osc;
+mod midi;
+mod dbus;
+pub mod cli;
+mod daemon;
+pub use app::{ScarlettControlApp, APP_ID};
\ No newline at end of file
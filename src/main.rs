@@ -9,7 +9,7 @@ fn main() -> eframe::Result {
     };
 
     eframe::run_native(
-        "scarlett-control",
+        scarlett_control::APP_ID,
         native_opts, 
         Box::new(|cc| Ok(Box::new(scarlett_control::ScarlettControlApp::new(cc))))
     )
@@ -0,0 +1,144 @@
+// the routing matrix as a grid, with matrix inputs down the side and mixes along the top, for seeing
+// at a glance what feeds each mix. the cells edit the destinations of the entries on each input, so
+// the grid and the cards are two views of the same entries
+
+use egui::RichText;
+use egui_material_icons::icons::{ICON_ADD, ICON_VOLUME_OFF, ICON_VOLUME_UP};
+
+use crate::{app::{gain_drag_value, AppState}, device::{matrix_layout, routes, Device}, midi::{self, Target}, state::{new_id, MixerDestination, MixerEntry, Port}, theme};
+
+enum CellAction {
+    Add(usize, Port),
+    Remove(usize, usize)
+}
+
+// the entry on each matrix input and which of its channels it is, if there is one
+fn inputs(state: &AppState, device: &Device) -> Vec<Option<(usize, usize)>> {
+    let mut inputs = vec![None; device.profile.matrix_inputs];
+    for (i, input) in matrix_layout(state, inputs.len()) {
+        let channels = if state.mixer_entries[i].stereo { 2 } else { 1 };
+        for c in 0..channels {
+            inputs[input + c] = Some((i, c));
+        }
+    }
+    inputs
+}
+
+// the destination of `entry` that sends its channel `c` to `mix`
+fn cell(entry: &MixerEntry, c: usize, mix: &Port) -> Option<usize> {
+    entry.dests.iter().position(|d| routes(entry.stereo, d).contains(&(c, mix)))
+}
+
+// send `entry` to `mix`. a stereo entry is folded down into it, since a destination takes both channels
+fn add_route(entry: &mut MixerEntry, mix: Port) {
+    entry.dests.push(MixerDestination {
+        stereo: false,
+        dest: mix.clone(),
+        dest_r: mix,
+        split: false,
+        gain: 0.0,
+        mute: false,
+        pan: 0.0,
+        solo: false,
+        id: new_id()
+    });
+}
+
+pub fn show(ui: &mut egui::Ui, state: &mut AppState, device: &Device) {
+    let inputs = inputs(state, device);
+    let mixes: Vec<Port> = device.mixer_destinations.iter().map(|m| Port::new(m)).collect();
+    let mut action = None;
+
+    egui::ScrollArea::horizontal().show(ui, |ui| {
+        egui::Grid::new("matrix_g").striped(true).spacing([12.0, 4.0]).show(ui, |ui| {
+            ui.label("");
+            ui.label("");
+            for mix in &mixes {
+                ui.label(RichText::new(&mix.0).strong());
+            }
+            ui.end_row();
+
+            for (n, input) in inputs.iter().enumerate() {
+                ui.label(RichText::new(format!("Matrix {:02}", n + 1)).weak());
+                let Some((i, c)) = *input else {
+                    ui.label(RichText::new("Off").color(theme::colors::TEXT_DISABLED));
+                    ui.end_row();
+                    continue;
+                };
+                let entry = &mut state.mixer_entries[i];
+                let channel = match (entry.stereo, c) {
+                    (false, _) => "",
+                    (true, 0) => " L",
+                    (true, _) => " R"
+                };
+                let name = format!("{}{}", entry.name, channel);
+                ui.label(&name);
+
+                for mix in &mixes {
+                    match cell(entry, c, mix) {
+                        Some(j) => {
+                            let id = entry.id;
+                            let d = &mut entry.dests[j];
+                            ui.horizontal(|ui| {
+                                let mute = egui_material_icons::icon_button(ui, if d.mute { ICON_VOLUME_OFF } else { ICON_VOLUME_UP });
+                                if mute.clicked() {
+                                    d.mute = !d.mute;
+                                }
+                                midi::menu(&mute, Target::DestMute(id, d.id));
+                                let gain = ui.add(gain_drag_value(&mut d.gain));
+                                midi::menu_with(&gain, Target::DestGain(id, d.id), |ui| {
+                                    if ui.button("Remove").clicked() {
+                                        action = Some(CellAction::Remove(i, j));
+                                        ui.close_menu();
+                                    }
+                                });
+                            });
+                        }
+                        None => {
+                            let add = egui::Button::new(egui_material_icons::icon_text(ICON_ADD).color(theme::colors::TEXT_DISABLED))
+                                .frame(false);
+                            if ui.add(add).on_hover_text(format!("Send {} to {}", name, mix.0)).clicked() {
+                                action = Some(CellAction::Add(i, mix.clone()));
+                            }
+                        }
+                    }
+                }
+                ui.end_row();
+            }
+        });
+    });
+
+    match action {
+        Some(CellAction::Add(i, mix)) => add_route(&mut state.mixer_entries[i], mix),
+        Some(CellAction::Remove(i, j)) => {
+            state.mixer_entries[i].dests.remove(j);
+        }
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::DeviceProfile;
+
+    use super::*;
+
+    #[test]
+    fn stereo_entries_fold_down_into_added_mixes() {
+        let device = Device::simulated(DeviceProfile::default_profile());
+        let mut state = AppState::default();
+        state.mixer_entries.push(MixerEntry::new(&device));
+        let mut stereo = MixerEntry::new(&device);
+        stereo.stereo = true;
+        stereo.dests.clear();
+        state.mixer_entries.push(stereo);
+
+        assert_eq!(inputs(&state, &device)[..3], [Some((0, 0)), Some((1, 0)), Some((1, 1))]);
+        let mix = Port::at(&device.mixer_destinations, 2);
+        add_route(&mut state.mixer_entries[1], mix.clone());
+        let entry = &state.mixer_entries[1];
+        assert_eq!(cell(entry, 0, &mix), Some(0));
+        assert_eq!(cell(entry, 1, &mix), Some(0));
+        assert_eq!(cell(entry, 0, &Port::at(&device.mixer_destinations, 3)), None);
+    }
+}
@@ -0,0 +1,431 @@
+// level meters for the capture channels, so anything routed to one can be metered: inputs directly, and
+// mixes by routing them to a capture channel too. they're off until they're turned on.
+//
+// the scarlett2 driver has a meter control, which is read where its order is known: from linux 6.8 it lists
+// the meters in port order, with the capture channels last. otherwise (gen 1, an older kernel, or if it
+// can't be read) the capture pcm is recorded from on a background thread. that needs the interface to
+// itself, so it isn't opened while something else has it, and no sample rate is asked for
+
+use std::{f32::consts::TAU, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, time::Duration};
+
+use alsa::{hctl::HCtl, pcm::{Access, Format, HwParams, State, PCM}, Direction, ValueOr};
+use egui::{pos2, vec2, Rect, Sense};
+
+use crate::{device::{Device, DeviceError}, profile::Generation, theme};
+
+// quieter than this shows as nothing
+const FLOOR_DB: f32 = -60.0;
+// how long the highest peak stays put before it starts falling
+const HOLD: f64 = 1.5;
+// how quickly meters fall back, in dB per second
+const FALL: f32 = 24.0;
+// a sample this close to full scale probably clipped
+const CLIP: f32 = 0.999;
+// of the simulated audio
+const RATE: u32 = 48000;
+const PERIOD: usize = 1024;
+// how long to wait for audio before checking whether to stop
+const WAIT_MS: u32 = 100;
+// the scarlett2 driver's meter control, with linear levels up to full scale
+const DRIVER_METER: &str = "Level Meter";
+const DRIVER_FULL_SCALE: f32 = 4095.0;
+// how often it's read
+const DRIVER_POLL: Duration = Duration::from_millis(50);
+
+const SIZE: egui::Vec2 = vec2(80.0, 6.0);
+const CLIP_WIDTH: f32 = 6.0;
+
+// peak and rms of a channel over a stretch of audio, as fractions of full scale
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Level {
+    pub peak: f32,
+    pub rms: f32
+}
+
+// what's been read on a channel since the ui last looked
+#[derive(Clone, Copy, Default)]
+struct Totals {
+    peak: f32,
+    squares: f64,
+    samples: u64
+}
+
+impl Totals {
+    fn add(&mut self, sample: f32) {
+        self.peak = self.peak.max(sample.abs());
+        self.squares += (sample * sample) as f64;
+        self.samples += 1;
+    }
+
+    fn level(&self) -> Level {
+        Level {
+            peak: self.peak,
+            rms: if self.samples == 0 { 0.0 } else { (self.squares / self.samples as f64).sqrt() as f32 }
+        }
+    }
+}
+
+struct Shared {
+    totals: Vec<Totals>,
+    error: Option<DeviceError>
+}
+
+impl Shared {
+    // `samples` are interleaved
+    fn add(&mut self, samples: &[f32]) {
+        let channels = self.totals.len();
+        debug_assert!(channels > 0);
+        for frame in samples.chunks_exact(channels) {
+            for (t, s) in self.totals.iter_mut().zip(frame) {
+                t.add(*s);
+            }
+        }
+    }
+}
+
+// reads levels on a background thread until it's dropped
+struct MeterReader {
+    shared: Arc<Mutex<Shared>>,
+    // cleared to stop the thread
+    running: Arc<AtomicBool>
+}
+
+impl MeterReader {
+    fn new(channels: usize, notify: Box<dyn Fn() + Send>, read: impl FnOnce(&Mutex<Shared>, &AtomicBool, &dyn Fn()) -> Result<(), DeviceError> + Send + 'static) -> Self {
+        let shared = Arc::new(Mutex::new(Shared {
+            totals: vec![Totals::default(); channels],
+            error: None
+        }));
+        let running = Arc::new(AtomicBool::new(true));
+        {
+            let (shared, running) = (shared.clone(), running.clone());
+            std::thread::spawn(move || {
+                if let Err(e) = read(&shared, &running, &*notify) {
+                    shared.lock().unwrap().error = Some(e);
+                    notify();
+                }
+            });
+        }
+        MeterReader { shared, running }
+    }
+
+    // levels of each channel since the last call
+    fn take(&self) -> Vec<Level> {
+        let mut shared = self.shared.lock().unwrap();
+        let levels = shared.totals.iter().map(Totals::level).collect();
+        shared.totals.fill(Totals::default());
+        levels
+    }
+}
+
+impl Drop for MeterReader {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+// a sample of `bytes.len()` little-endian bytes as a fraction of full scale
+fn decode(bytes: &[u8]) -> f32 {
+    // into the top of an i32, so every width has the same full scale
+    let mut word = [0; 4];
+    word[4 - bytes.len()..].copy_from_slice(bytes);
+    i32::from_le_bytes(word) as f32 / -(i32::MIN as f32)
+}
+
+// whether the scarlett2 driver in kernel `release` (eg. "6.8.0-31-generic") lists its meters in port order
+fn driver_meters_ordered(release: &str) -> bool {
+    let mut version = release.trim().split(['.', '-']).map(|n| n.parse::<u32>().unwrap_or(0));
+    (version.next().unwrap_or(0), version.next().unwrap_or(0)) >= (6, 8)
+}
+
+// the capture channels' levels from the driver's meter control on card `name`, which are the last `channels` meters
+fn read_driver(name: &str, channels: usize, shared: &Mutex<Shared>, running: &AtomicBool, notify: &dyn Fn()) -> Result<(), DeviceError> {
+    let err = |e| DeviceError::alsa(name, e);
+    let hctl = HCtl::new(name, false).map_err(err)?;
+    hctl.load().map_err(err)?;
+    let elem = hctl.elem_iter()
+        .find(|e| e.get_id().is_ok_and(|id| id.get_name() == Ok(DRIVER_METER)))
+        .ok_or_else(|| DeviceError::ControlMissing(DRIVER_METER.to_owned()))?;
+    let count = elem.info().map_err(err)?.get_count();
+    // with fewer than that they aren't laid out the way we think
+    if count == 0 || (count as usize) < channels {
+        return Err(DeviceError::Io(format!("{}: only {} meters for {} capture channels", name, count, channels)));
+    }
+    let first = count - channels as u32;
+
+    let mut levels = Vec::new();
+    while running.load(Ordering::Relaxed) {
+        let value = elem.read().map_err(err)?;
+        levels.clear();
+        levels.extend((first..count).map(|i| value.get_integer(i).unwrap_or(0) as f32 / DRIVER_FULL_SCALE));
+        shared.lock().unwrap().add(&levels);
+        notify();
+        std::thread::sleep(DRIVER_POLL);
+    }
+    Ok(())
+}
+
+fn read_capture(name: &str, channels: usize, shared: &Mutex<Shared>, running: &AtomicBool, notify: &dyn Fn()) -> Result<(), DeviceError> {
+    let err = |e| DeviceError::alsa(name, e);
+    // nonblocking, so it doesn't wait for whatever has the interface and then keep it from getting it back
+    let pcm = PCM::new(name, Direction::Capture, true).map_err(|e| match e.errno().abs() {
+        libc::EBUSY => DeviceError::Io(format!("{} is being recorded from by another program", name)),
+        _ => err(e)
+    })?;
+    let (format, channels) = {
+        let hwp = HwParams::any(&pcm).map_err(err)?;
+        hwp.set_access(Access::RWInterleaved).map_err(err)?;
+        // hw devices only take the number of channels they have
+        let channels = (channels as u32).clamp(hwp.get_channels_min().map_err(err)?, hwp.get_channels_max().map_err(err)?);
+        hwp.set_channels(channels).map_err(err)?;
+        // gen 1 is 24 bit packed into 3 bytes
+        let format = [Format::S32LE, Format::S243LE, Format::S16LE].into_iter()
+            .find(|f| hwp.test_format(*f).is_ok())
+            .ok_or_else(|| DeviceError::Io(format!("{}: no sample format we can read", name)))?;
+        hwp.set_format(format).map_err(err)?;
+        hwp.set_period_size_near(PERIOD as alsa::pcm::Frames, ValueOr::Nearest).map_err(err)?;
+        pcm.hw_params(&hwp).map_err(err)?;
+        (format, channels as usize)
+    };
+    let width = match format {
+        Format::S32LE => 4,
+        Format::S243LE => 3,
+        _ => 2
+    };
+    shared.lock().unwrap().totals.resize(channels, Totals::default());
+
+    let io = pcm.io_bytes();
+    let mut buf = vec![0; PERIOD * channels * width];
+    let mut samples = Vec::with_capacity(PERIOD * channels);
+    while running.load(Ordering::Relaxed) {
+        // capture otherwise only starts on a read, and there's nothing to read until it's started
+        if pcm.state() == State::Prepared {
+            pcm.start().map_err(err)?;
+        }
+        match pcm.wait(Some(WAIT_MS)) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                pcm.try_recover(e, true).map_err(err)?;
+                continue;
+            }
+        }
+        let frames = match io.readi(&mut buf) {
+            Ok(frames) => frames,
+            Err(e) if e.errno().abs() == libc::EAGAIN => continue,
+            // overruns from not keeping up are fine, we're only metering
+            Err(e) => {
+                pcm.try_recover(e, true).map_err(err)?;
+                continue;
+            }
+        };
+        samples.clear();
+        samples.extend(buf[..frames * channels * width].chunks_exact(width).map(decode));
+        shared.lock().unwrap().add(&samples);
+        notify();
+    }
+    Ok(())
+}
+
+// sines that slowly get louder and quieter, sometimes clipping, for trying meters out without hardware
+fn read_simulated(channels: usize, shared: &Mutex<Shared>, running: &AtomicBool, notify: &dyn Fn()) -> Result<(), DeviceError> {
+    const BLOCK: usize = 960;
+    // f64 so it doesn't lose precision after running for a while
+    let mut t = 0.0f64;
+    let mut samples = vec![0.0; BLOCK * channels];
+    while running.load(Ordering::Relaxed) {
+        for (i, frame) in samples.chunks_exact_mut(channels).enumerate() {
+            let t = (t + i as f64 / RATE as f64) as f32;
+            for (c, s) in frame.iter_mut().enumerate() {
+                let swell = (t * 0.2 * (c + 1) as f32).sin() * 0.5 + 0.5;
+                let db = -50.0 + 52.0 * swell * swell;
+                *s = (10f32.powf(db / 20.0) * (TAU * 220.0 * (c + 1) as f32 * t).sin()).clamp(-1.0, 1.0);
+            }
+        }
+        t = (t + BLOCK as f64 / RATE as f64) % 3600.0;
+        shared.lock().unwrap().add(&samples);
+        notify();
+        std::thread::sleep(Duration::from_secs_f32(BLOCK as f32 / RATE as f32));
+    }
+    Ok(())
+}
+
+fn to_db(level: f32) -> f32 {
+    (20.0 * level.log10()).max(FLOOR_DB)
+}
+
+// what's shown for a channel, falling back smoothly rather than jumping around with every block
+pub struct Meter {
+    peak: f32,
+    rms: f32,
+    hold: f32,
+    // when `hold` was last pushed up
+    held_at: f64,
+    // stays lit until it's clicked
+    clipped: bool
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Meter {
+            peak: FLOOR_DB,
+            rms: FLOOR_DB,
+            hold: FLOOR_DB,
+            held_at: 0.0,
+            clipped: false
+        }
+    }
+}
+
+impl Meter {
+    // `dt` seconds after the last update, at `now`
+    fn update(&mut self, level: Level, now: f64, dt: f32) {
+        let (peak, rms) = (to_db(level.peak), to_db(level.rms));
+        let fall = FALL * dt;
+        self.peak = peak.max(self.peak - fall);
+        self.rms = rms.max(self.rms - fall);
+        if peak >= self.hold {
+            self.hold = peak;
+            self.held_at = now;
+        } else if now - self.held_at > HOLD {
+            self.hold = (self.hold - fall).max(self.peak);
+        }
+        self.clipped |= level.peak >= CLIP;
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> egui::Response {
+        let (rect, response) = ui.allocate_exact_size(SIZE, Sense::click());
+        let bar = Rect::from_min_max(rect.min, pos2(rect.max.x - CLIP_WIDTH - 2.0, rect.max.y));
+        let x = |db: f32| bar.left() + bar.width() * (db - FLOOR_DB) / -FLOOR_DB;
+        let color = |db: f32| if db > -6.0 {
+            theme::colors::METER_HOT
+        } else if db > -18.0 {
+            theme::colors::METER_WARM
+        } else {
+            theme::colors::METER
+        };
+
+        let painter = ui.painter();
+        painter.rect_filled(bar, 1.0, theme::colors::BG2);
+        painter.rect_filled(Rect::from_min_max(bar.min, pos2(x(self.peak), bar.max.y)), 1.0, color(self.peak).gamma_multiply(0.5));
+        painter.rect_filled(Rect::from_min_max(bar.min, pos2(x(self.rms), bar.max.y)), 1.0, color(self.rms));
+        if self.hold > FLOOR_DB {
+            let hold = Rect::from_center_size(pos2(x(self.hold), bar.center().y), vec2(2.0, bar.height()));
+            painter.rect_filled(hold, 0.0, color(self.hold));
+        }
+        let clip = Rect::from_min_max(pos2(rect.max.x - CLIP_WIDTH, rect.min.y), rect.max);
+        painter.rect_filled(clip, 1.0, if self.clipped { theme::colors::CLIP } else { theme::colors::BG2 });
+
+        if response.clicked() {
+            self.clipped = false;
+        }
+        response.on_hover_text(if self.clipped {
+            format!("Peak {:.1} dB, clipped (click to reset)", self.hold)
+        } else {
+            format!("Peak {:.1} dB", self.hold)
+        })
+    }
+}
+
+// an empty meter for something that can't be metered
+pub fn unavailable(ui: &mut egui::Ui, why: &str) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(SIZE, Sense::hover());
+    ui.painter().rect_stroke(rect, 1.0, egui::Stroke::new(1.0, theme::colors::BG2));
+    response.on_hover_text(why)
+}
+
+// a meter for each capture channel of a device
+pub struct Meters {
+    // id of the device they're for
+    device: String,
+    reader: MeterReader,
+    meters: Vec<Meter>,
+    last: Option<f64>
+}
+
+impl Meters {
+    // `notify` is called from a background thread when there are new levels
+    pub fn new(device: &Device, notify: Box<dyn Fn() + Send>) -> Self {
+        let channels = device.profile.capture_channels;
+        let driver = device.profile.generation != Generation::Gen1
+            && driver_meters_ordered(&std::fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default());
+        let reader = match device.pcm() {
+            // recording from it is the next best thing if the driver's meters can't be read
+            Some(card) if driver => MeterReader::new(channels, notify, move |shared, running, notify| read_driver(&card, channels, shared, running, notify)
+                .or_else(|_| read_capture(&card, channels, shared, running, notify))),
+            Some(pcm) => MeterReader::new(channels, notify, move |shared, running, notify| read_capture(&pcm, channels, shared, running, notify)),
+            None => MeterReader::new(channels, notify, move |shared, running, notify| read_simulated(channels, shared, running, notify))
+        };
+        Meters {
+            device: device.id.clone(),
+            reader,
+            meters: (0..channels).map(|_| Meter::default()).collect(),
+            last: None
+        }
+    }
+
+    pub fn is_for(&self, device: &Device) -> bool {
+        self.device == device.id
+    }
+
+    // bring the meters up to date, at `now` seconds
+    pub fn update(&mut self, now: f64) {
+        let dt = self.last.map_or(0.0, |last| (now - last) as f32);
+        self.last = Some(now);
+        for (m, level) in self.meters.iter_mut().zip(self.reader.take()) {
+            m.update(level, now, dt);
+        }
+    }
+
+    pub fn channel(&mut self, i: usize) -> Option<&mut Meter> {
+        self.meters.get_mut(i)
+    }
+
+    // why there aren't any levels, if something went wrong
+    pub fn error(&self) -> Option<DeviceError> {
+        self.reader.shared.lock().unwrap().error.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_every_width_to_the_same_scale() {
+        assert_eq!(decode(&i16::MIN.to_le_bytes()), -1.0);
+        assert_eq!(decode(&[0, 0, 0x40]), 0.5);
+        assert_eq!(decode(&(i32::MAX / 4 + 1).to_le_bytes()), 0.25);
+    }
+
+    #[test]
+    fn uses_driver_meters_from_6_8() {
+        assert!(driver_meters_ordered("6.8.0-31-generic\n"));
+        assert!(driver_meters_ordered("6.11.2-arch1-1"));
+        assert!(!driver_meters_ordered("6.7.12"));
+        assert!(!driver_meters_ordered("5.15.0-105-generic"));
+        assert!(!driver_meters_ordered(""));
+    }
+
+    #[test]
+    fn holds_peaks_and_latches_clips() {
+        let mut totals = Totals::default();
+        for s in [1.0, -1.0, 1.0, -1.0] {
+            totals.add(s);
+        }
+        assert_eq!(totals.level(), Level { peak: 1.0, rms: 1.0 });
+
+        let mut meter = Meter::default();
+        meter.update(totals.level(), 0.0, 0.0);
+        assert!(meter.clipped);
+        assert_eq!(meter.hold, 0.0);
+        // quiet, but still inside the hold time
+        let quiet = Level { peak: 0.01, rms: 0.01 };
+        meter.update(quiet, 1.0, 1.0);
+        assert_eq!(meter.hold, 0.0);
+        assert_eq!(meter.peak, -FALL);
+        meter.update(quiet, 2.0, 1.0);
+        assert_eq!(meter.hold, -FALL);
+        assert!(meter.clipped);
+    }
+}
@@ -0,0 +1,506 @@
+// midi control surfaces, through the alsa sequencer. right-click a fader, mute or hi-z switch and pick
+// "MIDI learn", then move a fader or press a button on the controller to map it to that. faders pick up
+// the value where it is rather than jumping to wherever they are (soft takeover), and with feedback on,
+// mapped values are sent back out as they change so motor faders and button leds follow along.
+//
+// gains are mapped from 0-127 along the same scale as the app's faders. notes toggle switches when
+// they're pressed, and ccs turn them on from 64 up, which suits buttons that latch
+
+use std::{collections::HashMap, fmt, sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc}, thread::JoinHandle};
+
+use alsa::{poll::Descriptors, seq::{EvCtrl, EvNote, Event, EventType, PortCap, PortType, Seq}, Direction};
+
+use crate::{app::AppState, fader, state::{Id, MixerDestination, MixerEntry}};
+
+// how long the thread waits for events before sending feedback or checking whether it should stop
+const POLL_MS: i32 = 20;
+// how many steps a fader can be from the value it controls and still pick it up
+const TAKEOVER: f32 = 3.0;
+
+// a fader, knob or button on the controller. channels count from 0, like they're sent
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Control {
+    Cc { channel: u8, number: u8 },
+    Note { channel: u8, number: u8 }
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Control::Cc { channel, number } => write!(f, "CC {} on channel {}", number, channel + 1),
+            Control::Note { channel, number } => write!(f, "note {} on channel {}", number, channel + 1)
+        }
+    }
+}
+
+// something in the app a control can be mapped to. indices count from 0, and mixer entries go by their
+// ids so mappings stay with them when others are added, removed or undone
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy, Debug)]
+pub enum Target {
+    GlobalGain,
+    GlobalMute,
+    OutputGain(usize),
+    OutputMute(usize),
+    EntryMute(Id),
+    // a destination of a mixer entry, by the entry's id and its own
+    DestGain(Id, Id),
+    DestMute(Id, Id),
+    // in the order the device profile lists the inputs with the switch
+    HiZ(usize)
+}
+
+enum Value<'a> {
+    Gain(&'a mut f32),
+    Switch(&'a mut bool)
+}
+
+impl Target {
+    fn is_gain(self) -> bool {
+        matches!(self, Target::GlobalGain | Target::OutputGain(_) | Target::DestGain(..))
+    }
+
+    fn value_mut(self, state: &mut AppState) -> Option<Value<'_>> {
+        Some(match self {
+            Target::GlobalGain => Value::Gain(&mut state.global_gain),
+            Target::GlobalMute => Value::Switch(&mut state.global_mute),
+            Target::OutputGain(i) => Value::Gain(&mut state.outputs.get_mut(i)?.gain),
+            Target::OutputMute(i) => Value::Switch(&mut state.outputs.get_mut(i)?.mute),
+            Target::EntryMute(e) => Value::Switch(&mut entry_mut(state, e)?.mute),
+            Target::DestGain(e, d) => Value::Gain(&mut dest_mut(state, e, d)?.gain),
+            Target::DestMute(e, d) => Value::Switch(&mut dest_mut(state, e, d)?.mute),
+            Target::HiZ(i) => Value::Switch(state.hi_z.get_mut(i)?)
+        })
+    }
+
+    // what to send a control mapped to this, or none if it isn't there any more
+    fn feedback(self, state: &AppState) -> Option<u8> {
+        let gain = |db: f32| (fader::position(db) * 127.0).round() as u8;
+        let switch = |on: bool| if on { 127 } else { 0 };
+        Some(match self {
+            Target::GlobalGain => gain(state.global_gain),
+            Target::GlobalMute => switch(state.global_mute),
+            Target::OutputGain(i) => gain(state.outputs.get(i)?.gain),
+            Target::OutputMute(i) => switch(state.outputs.get(i)?.mute),
+            Target::EntryMute(e) => switch(entry(state, e)?.mute),
+            Target::DestGain(e, d) => gain(dest(state, e, d)?.gain),
+            Target::DestMute(e, d) => switch(dest(state, e, d)?.mute),
+            Target::HiZ(i) => switch(*state.hi_z.get(i)?)
+        })
+    }
+
+    // set from `value`, given the last value from the same control. returns whether anything changed
+    fn apply(self, state: &mut AppState, control: Control, value: u8, last: Option<u8>) -> bool {
+        match (self.value_mut(state), control) {
+            (Some(Value::Gain(gain)), _) => {
+                let (v, current) = (value as f32, fader::position(*gain) * 127.0);
+                // once it's reached the value, or passed it on the way somewhere else
+                let near = |v: f32| (v - current).abs() <= TAKEOVER;
+                let caught = last.is_some_and(|l| near(l as f32) || (l as f32 - current) * (v - current) < 0.0);
+                if !near(v) && !caught {
+                    return false;
+                }
+                *gain = fader::gain(v / 127.0);
+            }
+            (Some(Value::Switch(on)), Control::Note { .. }) => {
+                // a note off
+                if value == 0 {
+                    return false;
+                }
+                *on = !*on;
+            }
+            (Some(Value::Switch(on)), Control::Cc { .. }) => *on = value >= 64,
+            (None, _) => return false
+        }
+        true
+    }
+}
+
+// the entry with id `id`, if it's still there
+fn entry(state: &AppState, id: Id) -> Option<&MixerEntry> {
+    state.mixer_entries.iter().find(|e| e.id == id)
+}
+
+fn entry_mut(state: &mut AppState, id: Id) -> Option<&mut MixerEntry> {
+    state.mixer_entries.iter_mut().find(|e| e.id == id)
+}
+
+// destination `id` of entry `entry`
+fn dest(state: &AppState, entry: Id, id: Id) -> Option<&MixerDestination> {
+    self::entry(state, entry)?.dests.iter().find(|d| d.id == id)
+}
+
+fn dest_mut(state: &mut AppState, entry: Id, id: Id) -> Option<&mut MixerDestination> {
+    entry_mut(state, entry)?.dests.iter_mut().find(|d| d.id == id)
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy, Debug)]
+pub struct Mapping {
+    pub control: Control,
+    pub target: Target
+}
+
+// map `control` to `target`, instead of whatever either was mapped to before
+fn learn(mappings: &mut Vec<Mapping>, control: Control, target: Target) {
+    mappings.retain(|m| m.control != control && m.target != target);
+    mappings.push(Mapping { control, target });
+}
+
+// what the context menus need to know, and what they asked for, kept in egui's temp data
+#[derive(Clone, Default)]
+struct Shared {
+    available: bool,
+    mappings: Vec<Mapping>,
+    learning: Option<Target>,
+    request: Option<Request>
+}
+
+#[derive(Clone, Copy)]
+enum Request {
+    Learn(Target),
+    Cancel,
+    Forget(Target)
+}
+
+fn shared_id() -> egui::Id {
+    egui::Id::new("midi")
+}
+
+// add "MIDI learn" to the context menu of whatever controls `target`
+pub fn menu(response: &egui::Response, target: Target) {
+    let shared: Shared = response.ctx.data(|d| d.get_temp(shared_id())).unwrap_or_default();
+    if shared.available {
+        context_menu(response, target, shared, |_| {});
+    }
+}
+
+// `menu` for something with a context menu of its own, which `add_contents` puts first
+pub fn menu_with(response: &egui::Response, target: Target, add_contents: impl FnOnce(&mut egui::Ui)) {
+    let shared: Shared = response.ctx.data(|d| d.get_temp(shared_id())).unwrap_or_default();
+    context_menu(response, target, shared, add_contents);
+}
+
+fn context_menu(response: &egui::Response, target: Target, shared: Shared, add_contents: impl FnOnce(&mut egui::Ui)) {
+    let ctx = &response.ctx;
+    let mut request = None;
+    response.context_menu(|ui| {
+        add_contents(ui);
+        if !shared.available {
+            return;
+        }
+        if shared.learning == Some(target) {
+            if ui.button("Cancel MIDI learn").clicked() {
+                request = Some(Request::Cancel);
+                ui.close_menu();
+            }
+        } else if ui.button("MIDI learn").clicked() {
+            request = Some(Request::Learn(target));
+            ui.close_menu();
+        }
+        if let Some(m) = shared.mappings.iter().find(|m| m.target == target) {
+            ui.label(egui::RichText::new(format!("Mapped to {}", m.control)).weak());
+            if ui.button("Forget MIDI").clicked() {
+                request = Some(Request::Forget(target));
+                ui.close_menu();
+            }
+        }
+    });
+    if request.is_some() {
+        ctx.data_mut(|d| d.get_temp_mut_or_default::<Shared>(shared_id()).request = request);
+    }
+}
+
+// the sequencer port, read and written from a background thread
+struct Sequencer {
+    received: mpsc::Receiver<(Control, u8)>,
+    outgoing: mpsc::Sender<(Control, u8)>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Sequencer {
+    // `notify` is called from the thread when something arrives
+    fn new(notify: Box<dyn Fn() + Send>) -> alsa::Result<Sequencer> {
+        let seq = Seq::open(None, None, true)?;
+        seq.set_client_name(c"Scarlett Control")?;
+        let port = seq.create_simple_port(
+            c"Control",
+            PortCap::READ | PortCap::SUBS_READ | PortCap::WRITE | PortCap::SUBS_WRITE,
+            PortType::MIDI_GENERIC | PortType::APPLICATION
+        )?;
+        let running = Arc::new(AtomicBool::new(true));
+        let (tx, received) = mpsc::channel();
+        let (outgoing, rx) = mpsc::channel::<(Control, u8)>();
+        let thread = {
+            let running = running.clone();
+            std::thread::spawn(move || {
+                while running.load(Ordering::Relaxed) {
+                    if let Ok(mut fds) = (&seq, Some(Direction::Capture)).get() {
+                        let _ = alsa::poll::poll(&mut fds, POLL_MS);
+                    }
+                    let mut input = seq.input();
+                    let mut any = false;
+                    // an error here is usually events being dropped because too many came at once
+                    while input.event_input_pending(true).unwrap_or(0) > 0 {
+                        let Ok(ev) = input.event_input() else {
+                            break;
+                        };
+                        if let Some(e) = incoming(&ev) {
+                            let _ = tx.send(e);
+                            any = true;
+                        }
+                    }
+                    if any {
+                        notify();
+                    }
+                    for (control, value) in rx.try_iter() {
+                        let mut ev = match control {
+                            Control::Cc { channel, number } => Event::new(EventType::Controller, &EvCtrl { channel, param: number.into(), value: value.into() }),
+                            Control::Note { channel, number } => Event::new(EventType::Noteon, &EvNote { channel, note: number, velocity: value, off_velocity: 0, duration: 0 })
+                        };
+                        ev.set_source(port);
+                        ev.set_subs();
+                        ev.set_direct();
+                        if let Err(e) = seq.event_output_direct(&mut ev) {
+                            log::debug!("couldn't send midi: {}", e);
+                        }
+                    }
+                }
+            })
+        };
+        Ok(Sequencer { received, outgoing, running, thread: Some(thread) })
+    }
+}
+
+// the control and value of a cc or note, with note offs as 0
+fn incoming(ev: &Event) -> Option<(Control, u8)> {
+    match ev.get_type() {
+        EventType::Controller => {
+            let c: EvCtrl = ev.get_data()?;
+            let number = u8::try_from(c.param).ok().filter(|n| *n < 128)?;
+            Some((Control::Cc { channel: c.channel, number }, c.value.clamp(0, 127) as u8))
+        }
+        EventType::Noteon | EventType::Noteoff => {
+            let n: EvNote = ev.get_data()?;
+            let velocity = if ev.get_type() == EventType::Noteon { n.velocity } else { 0 };
+            Some((Control::Note { channel: n.channel, number: n.note }, velocity))
+        }
+        _ => None
+    }
+}
+
+impl Drop for Sequencer {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub struct Midi {
+    pub enabled: bool,
+    // send mapped values back to the controller
+    pub feedback: bool,
+    pub mappings: Vec<Mapping>,
+    // what the next control to be moved gets mapped to
+    pub learning: Option<Target>,
+    sequencer: Option<Sequencer>,
+    // why the sequencer couldn't be opened, which isn't tried again until midi is turned off and on
+    pub error: Option<String>,
+    // the last value from each control, for soft takeover
+    received: HashMap<Control, u8>,
+    // what each control was last sent
+    sent: HashMap<Control, u8>
+}
+
+impl Midi {
+    pub fn new(enabled: bool, feedback: bool, mappings: Vec<Mapping>) -> Self {
+        Midi {
+            enabled,
+            feedback,
+            mappings,
+            learning: None,
+            sequencer: None,
+            error: None,
+            received: HashMap::new(),
+            sent: HashMap::new()
+        }
+    }
+
+    // open or close the sequencer to match `enabled`, and let the context menus know what's mapped
+    pub fn run(&mut self, ctx: &egui::Context) {
+        if !self.enabled {
+            self.sequencer = None;
+            self.error = None;
+            self.learning = None;
+        } else if self.sequencer.is_none() && self.error.is_none() {
+            let notify = ctx.clone();
+            match Sequencer::new(Box::new(move || notify.request_repaint())) {
+                Ok(sequencer) => {
+                    log::info!("midi port open");
+                    self.sequencer = Some(sequencer);
+                    self.sent.clear();
+                }
+                Err(e) => self.error = Some(e.to_string())
+            }
+        }
+        if !self.feedback {
+            self.sent.clear();
+        }
+        let shared = Shared {
+            available: self.sequencer.is_some(),
+            mappings: self.mappings.clone(),
+            learning: self.learning,
+            request: None
+        };
+        ctx.data_mut(|d| d.insert_temp(shared_id(), shared));
+    }
+
+    // act on what the context menus asked for this frame
+    pub fn requests(&mut self, ctx: &egui::Context) {
+        match ctx.data_mut(|d| d.get_temp_mut_or_default::<Shared>(shared_id()).request.take()) {
+            Some(Request::Learn(target)) => self.learning = Some(target),
+            Some(Request::Cancel) => self.learning = None,
+            Some(Request::Forget(target)) => self.mappings.retain(|m| m.target != target),
+            None => {}
+        }
+    }
+
+    // apply what's arrived since the last call to `state`, or map it to what's being learnt
+    pub fn receive(&mut self, state: &mut AppState) {
+        let Some(sequencer) = &self.sequencer else {
+            return;
+        };
+        for (control, value) in sequencer.received.try_iter() {
+            let last = self.received.insert(control, value);
+            if let Some(target) = self.learning {
+                // gains need something that moves, rather than a button
+                let usable = if target.is_gain() { matches!(control, Control::Cc { .. }) } else { value > 0 };
+                if usable {
+                    log::info!("mapped {} to {:?}", control, target);
+                    learn(&mut self.mappings, control, target);
+                    self.learning = None;
+                }
+                continue;
+            }
+            let Some(m) = self.mappings.iter().find(|m| m.control == control) else {
+                continue;
+            };
+            if m.target.apply(state, control, value, last) && m.target.is_gain() {
+                // it's already there, so sending it back would only fight whoever's moving it
+                self.sent.insert(control, value);
+            }
+        }
+    }
+
+    // send the controller whatever's changed in `state`, with feedback on
+    pub fn send(&mut self, state: &AppState) {
+        let Some(sequencer) = &self.sequencer else {
+            return;
+        };
+        if !self.feedback {
+            return;
+        }
+        for m in &self.mappings {
+            let Some(value) = m.target.feedback(state) else {
+                continue;
+            };
+            if self.sent.insert(m.control, value) != Some(value) {
+                let _ = sequencer.outgoing.send((m.control, value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{device::Device, history::History, profile::DeviceProfile};
+
+    use super::*;
+
+    fn device() -> Device {
+        Device::simulated(DeviceProfile::find("Scarlett 18i6").unwrap())
+    }
+
+    fn state() -> AppState {
+        let device = device();
+        device.known().to_state(&device)
+    }
+
+    #[test]
+    fn faders_pick_up_the_gain_where_it_is() {
+        let mut state = state();
+        let cc = Control::Cc { channel: 0, number: 7 };
+        let target = Target::OutputGain(0);
+        state.outputs[0].gain = 0.0;
+        // 0dB is at 101.6
+        assert!(!target.apply(&mut state, cc, 20, None));
+        assert!(!target.apply(&mut state, cc, 90, Some(20)));
+        assert_eq!(state.outputs[0].gain, 0.0);
+        assert!(target.apply(&mut state, cc, 110, Some(90)));
+        assert!((state.outputs[0].gain - fader::gain(110.0 / 127.0)).abs() < 0.001);
+        assert!(target.apply(&mut state, cc, 127, Some(110)));
+        assert_eq!(target.feedback(&state), Some(127));
+
+        state.outputs[0].gain = 0.0;
+        assert!(target.apply(&mut state, cc, 100, None));
+        assert_eq!(target.feedback(&state), Some(100));
+    }
+
+    #[test]
+    fn notes_toggle_switches_and_ccs_set_them() {
+        let mut state = state();
+        let note = Control::Note { channel: 9, number: 36 };
+        let target = Target::HiZ(0);
+        assert!(target.apply(&mut state, note, 100, None));
+        assert!(!target.apply(&mut state, note, 0, Some(100)));
+        assert!(state.hi_z[0]);
+        assert!(target.apply(&mut state, note, 1, Some(0)));
+        assert!(!state.hi_z[0]);
+
+        let cc = Control::Cc { channel: 0, number: 20 };
+        assert!(target.apply(&mut state, cc, 127, None));
+        assert!(target.apply(&mut state, cc, 127, Some(127)));
+        assert!(state.hi_z[0]);
+        assert_eq!(target.feedback(&state), Some(127));
+        assert!(!Target::HiZ(10).apply(&mut state, cc, 127, None));
+    }
+
+    #[test]
+    fn learning_replaces_old_mappings() {
+        let a = Control::Cc { channel: 0, number: 1 };
+        let b = Control::Cc { channel: 0, number: 2 };
+        let mut mappings = Vec::new();
+        learn(&mut mappings, a, Target::GlobalGain);
+        learn(&mut mappings, b, Target::OutputGain(0));
+        // the same control somewhere else
+        learn(&mut mappings, a, Target::DestGain(0, 1));
+        // and something else for the same target
+        learn(&mut mappings, a, Target::OutputGain(0));
+        assert_eq!(mappings, [Mapping { control: a, target: Target::OutputGain(0) }]);
+    }
+
+    #[test]
+    fn mappings_follow_entries_when_removing_one_is_undone() {
+        let device = device();
+        let mut state = device.known().to_state(&device);
+        state.mixer_entries = vec![MixerEntry::new(&device), MixerEntry::new(&device)];
+        let second = &state.mixer_entries[1];
+        let (mute, gain) = (Target::EntryMute(second.id), Target::DestGain(second.id, second.dests[0].id));
+        let note = Control::Note { channel: 0, number: 1 };
+        let mut history = History::default();
+
+        let before = state.clone();
+        state.mixer_entries.remove(0);
+        history.record(before, &state, false);
+        assert!(mute.apply(&mut state, note, 127, None));
+        assert!(state.mixer_entries[0].mute);
+
+        history.undo(&mut state);
+        assert_eq!(state.mixer_entries.len(), 2);
+        assert!(!state.mixer_entries[0].mute && state.mixer_entries[1].mute);
+        assert_eq!(mute.feedback(&state), Some(127));
+        state.mixer_entries[1].dests[0].gain = 6.0;
+        assert_eq!(gain.feedback(&state), Some(127));
+    }
+}
@@ -0,0 +1,374 @@
+// an osc server over udp, for controlling mixes from eg. TouchOSC on a tablet. numbers in addresses
+// count from 1, like the app does:
+//
+//   /global/gain, /global/fader, /global/mute
+//   /output/{output}/gain, /fader, /mute
+//   /mixer/{entry}/dest/{destination}/gain, /fader, /mute
+//   /input/{input}/hiz, /pad, /air
+//
+// gains are in dB, and faders go from 0 to 1 along the same scale as the app's faders. switches are 0 or 1,
+// and anything but 0 turns them on. whoever sends something gets sent every value whenever it changes,
+// from anywhere, and /sync (with no arguments) sends everything now
+
+use std::{collections::HashMap, io, net::{SocketAddr, UdpSocket}, sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc}, thread::JoinHandle, time::Duration};
+
+use crate::{app::AppState, device::{Device, GAIN_MIN}, fader::{self, GAIN_MAX}};
+
+pub const DEFAULT_PORT: u16 = 9000;
+
+// how long the receiving thread waits for a packet before checking whether it should stop
+const POLL: Duration = Duration::from_millis(200);
+// the oldest client is forgotten when there are more than this
+const MAX_CLIENTS: usize = 8;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Arg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Bool(bool)
+}
+
+impl Arg {
+    fn as_f32(&self) -> Option<f32> {
+        match self {
+            Arg::Int(i) => Some(*i as f32),
+            Arg::Float(f) => Some(*f),
+            Arg::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            Arg::Str(_) => None
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Message {
+    pub addr: String,
+    pub args: Vec<Arg>
+}
+
+// osc strings are null terminated and padded to 4 bytes
+fn push_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    out.extend(std::iter::repeat_n(0, 4 - s.len() % 4));
+}
+
+fn read_str(packet: &[u8], at: &mut usize) -> Option<String> {
+    let len = packet.get(*at..)?.iter().position(|b| *b == 0)?;
+    let s = std::str::from_utf8(&packet[*at..*at + len]).ok()?.to_owned();
+    *at += (len / 4 + 1) * 4;
+    Some(s)
+}
+
+fn read_4(packet: &[u8], at: &mut usize) -> Option<[u8; 4]> {
+    let bytes = packet.get(*at..*at + 4)?.try_into().ok()?;
+    *at += 4;
+    Some(bytes)
+}
+
+impl Message {
+    pub fn new(addr: impl Into<String>, args: Vec<Arg>) -> Self {
+        Message { addr: addr.into(), args }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        push_str(&mut out, &self.addr);
+        let tags: String = std::iter::once(',').chain(self.args.iter().map(|a| match a {
+            Arg::Int(_) => 'i',
+            Arg::Float(_) => 'f',
+            Arg::Str(_) => 's',
+            Arg::Bool(true) => 'T',
+            Arg::Bool(false) => 'F'
+        })).collect();
+        push_str(&mut out, &tags);
+        for a in &self.args {
+            match a {
+                Arg::Int(i) => out.extend_from_slice(&i.to_be_bytes()),
+                Arg::Float(f) => out.extend_from_slice(&f.to_be_bytes()),
+                Arg::Str(s) => push_str(&mut out, s),
+                Arg::Bool(_) => {}
+            }
+        }
+        out
+    }
+
+    // the messages in a packet, which is one message or a bundle of them. anything that can't be read is left out
+    pub fn decode(packet: &[u8]) -> Vec<Message> {
+        let mut at = 0;
+        if packet.starts_with(b"#bundle\0") {
+            // skip the time tag, bundles are handled as soon as they arrive
+            at = 16;
+            let mut messages = Vec::new();
+            while let Some(len) = read_4(packet, &mut at).map(|b| i32::from_be_bytes(b).max(0) as usize) {
+                let Some(element) = packet.get(at..at + len) else {
+                    break;
+                };
+                messages.extend(Message::decode(element));
+                at += len;
+            }
+            return messages;
+        }
+        Message::decode_one(packet, &mut at).into_iter().collect()
+    }
+
+    fn decode_one(packet: &[u8], at: &mut usize) -> Option<Message> {
+        let addr = read_str(packet, at)?;
+        if !addr.starts_with('/') {
+            return None;
+        }
+        // some old clients leave out the type tags when there aren't any arguments
+        let tags = if *at < packet.len() { read_str(packet, at)? } else { ",".to_owned() };
+        let mut args = Vec::new();
+        for tag in tags.strip_prefix(',')?.chars() {
+            args.push(match tag {
+                'i' => Arg::Int(i32::from_be_bytes(read_4(packet, at)?)),
+                'f' => Arg::Float(f32::from_be_bytes(read_4(packet, at)?)),
+                's' => Arg::Str(read_str(packet, at)?),
+                'T' => Arg::Bool(true),
+                'F' => Arg::Bool(false),
+                _ => return None
+            });
+        }
+        Some(Message { addr, args })
+    }
+}
+
+// every address and what it's set to in `state`
+fn values(state: &AppState, device: &Device) -> Vec<(String, f32)> {
+    let switch = |on: bool| if on { 1.0 } else { 0.0 };
+    let mut v = Vec::new();
+    let mut gain = |path: String, db: f32, mute: bool| {
+        v.push((format!("{path}/gain"), db));
+        v.push((format!("{path}/fader"), fader::position(db)));
+        v.push((format!("{path}/mute"), switch(mute)));
+    };
+    gain("/global".to_owned(), state.global_gain, state.global_mute);
+    for (i, o) in state.outputs.iter().enumerate() {
+        gain(format!("/output/{}", i + 1), o.gain, o.mute);
+    }
+    for (i, e) in state.mixer_entries.iter().enumerate() {
+        for (j, d) in e.dests.iter().enumerate() {
+            gain(format!("/mixer/{}/dest/{}", i + 1, j + 1), d.gain, d.mute);
+        }
+    }
+    let p = device.profile;
+    for (kind, inputs, on) in [("hiz", p.hi_z, &state.hi_z), ("pad", p.pad, &state.pad), ("air", p.air, &state.air)] {
+        for (n, on) in inputs.iter().zip(on) {
+            v.push((format!("/input/{n}/{kind}"), switch(*on)));
+        }
+    }
+    v
+}
+
+// set what `msg` is addressed to, returning false if it isn't anything
+fn apply(state: &mut AppState, device: &Device, msg: &Message) -> bool {
+    let Some(value) = msg.args.first().and_then(Arg::as_f32) else {
+        return false;
+    };
+    let parts: Vec<&str> = msg.addr.trim_start_matches('/').split('/').collect();
+    // the one counting from 1 at `i`, if there is one
+    let index = |i: usize| parts.get(i).and_then(|n| n.parse::<usize>().ok()).and_then(|n| n.checked_sub(1));
+    let (gain, mute, control) = match parts[..] {
+        ["global", control] => (&mut state.global_gain, &mut state.global_mute, control),
+        ["output", _, control] => {
+            let Some(o) = index(1).and_then(|i| state.outputs.get_mut(i)) else {
+                return false;
+            };
+            (&mut o.gain, &mut o.mute, control)
+        }
+        ["mixer", _, "dest", _, control] => {
+            let Some(d) = index(1).and_then(|i| state.mixer_entries.get_mut(i)).and_then(|e| index(3).and_then(|j| e.dests.get_mut(j))) else {
+                return false;
+            };
+            (&mut d.gain, &mut d.mute, control)
+        }
+        ["input", n, kind] => {
+            let p = device.profile;
+            let (inputs, on) = match kind {
+                "hiz" => (p.hi_z, &mut state.hi_z),
+                "pad" => (p.pad, &mut state.pad),
+                "air" => (p.air, &mut state.air),
+                _ => return false
+            };
+            let Some(on) = n.parse::<usize>().ok().and_then(|n| inputs.iter().position(|i| *i == n)).and_then(|i| on.get_mut(i)) else {
+                return false;
+            };
+            *on = value != 0.0;
+            return true;
+        }
+        _ => return false
+    };
+    match control {
+        "gain" => *gain = value.clamp(GAIN_MIN, GAIN_MAX),
+        "fader" => *gain = fader::gain(value),
+        "mute" => *mute = value != 0.0,
+        _ => return false
+    }
+    true
+}
+
+pub struct OscServer {
+    socket: UdpSocket,
+    received: mpsc::Receiver<(Message, SocketAddr)>,
+    // who gets feedback, most recent last
+    clients: Vec<SocketAddr>,
+    // clients that want everything, rather than just what's changed
+    syncing: Vec<SocketAddr>,
+    // what each address was last sent as
+    sent: HashMap<String, f32>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl OscServer {
+    // listen on `port` on every interface. `notify` is called from a background thread when something arrives
+    pub fn new(port: u16, notify: Box<dyn Fn() + Send>) -> io::Result<OscServer> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        let receiver = socket.try_clone()?;
+        receiver.set_read_timeout(Some(POLL))?;
+        let running = Arc::new(AtomicBool::new(true));
+        let (tx, received) = mpsc::channel();
+        let thread = {
+            let running = running.clone();
+            std::thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while running.load(Ordering::Relaxed) {
+                    let Ok((len, from)) = receiver.recv_from(&mut buf) else {
+                        continue;
+                    };
+                    for msg in Message::decode(&buf[..len]) {
+                        let _ = tx.send((msg, from));
+                    }
+                    notify();
+                }
+            })
+        };
+        Ok(OscServer {
+            socket,
+            received,
+            clients: Vec::new(),
+            syncing: Vec::new(),
+            sent: HashMap::new(),
+            running,
+            thread: Some(thread)
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.socket.local_addr().map_or(0, |a| a.port())
+    }
+
+    // apply what's arrived since the last call to `state`
+    pub fn receive(&mut self, state: &mut AppState, device: &Device) {
+        for (msg, from) in self.received.try_iter().collect::<Vec<_>>() {
+            if !self.clients.contains(&from) {
+                if self.clients.len() == MAX_CLIENTS {
+                    self.clients.remove(0);
+                }
+                self.clients.push(from);
+                self.syncing.push(from);
+            }
+            if msg.addr == "/sync" {
+                self.syncing.push(from);
+            } else if !apply(state, device, &msg) {
+                log::debug!("ignoring osc message {:?} from {}", msg, from);
+            }
+        }
+    }
+
+    // send clients whatever's changed in `state`
+    pub fn send(&mut self, state: &AppState, device: &Device) {
+        if self.clients.is_empty() {
+            return;
+        }
+        let syncing = std::mem::take(&mut self.syncing);
+        for (addr, v) in values(state, device) {
+            let changed = self.sent.insert(addr.clone(), v) != Some(v);
+            for client in self.clients.iter().filter(|c| changed || syncing.contains(c)) {
+                self.send_to(*client, &addr, v);
+            }
+        }
+    }
+
+    fn send_to(&self, client: SocketAddr, addr: &str, v: f32) {
+        let packet = Message::new(addr, vec![Arg::Float(v)]).encode();
+        if let Err(e) = self.socket.send_to(&packet, client) {
+            log::debug!("couldn't send osc to {}: {}", client, e);
+        }
+    }
+}
+
+impl Drop for OscServer {
+    // waits for the thread to stop waiting for a packet, so the port's free again afterwards
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::{profile::DeviceProfile, state::MixerEntry};
+
+    use super::*;
+
+    #[test]
+    fn messages_survive_encoding() {
+        let msg = Message::new("/mixer/1/dest/2/gain", vec![Arg::Float(-6.5), Arg::Int(3), Arg::Str("four".to_owned()), Arg::Bool(true)]);
+        let packet = msg.encode();
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(Message::decode(&packet), std::slice::from_ref(&msg));
+
+        let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        for _ in 0..2 {
+            bundle.extend_from_slice(&(packet.len() as i32).to_be_bytes());
+            bundle.extend_from_slice(&packet);
+        }
+        assert_eq!(Message::decode(&bundle), [msg.clone(), msg]);
+        assert!(Message::decode(b"nonsense").is_empty());
+    }
+
+    #[test]
+    fn a_local_client_can_change_gains_and_hears_back() {
+        let device = Device::simulated(DeviceProfile::find("Scarlett 18i6").unwrap());
+        let mut state = device.known().to_state(&device);
+        state.mixer_entries = vec![MixerEntry::new(&device)];
+        let mut server = OscServer::new(0, Box::new(|| {})).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let send = |msg: Message| client.send_to(&msg.encode(), ("127.0.0.1", server.port())).unwrap();
+        send(Message::new("/mixer/1/dest/1/gain", vec![Arg::Float(-10.0)]));
+        send(Message::new("/input/1/hiz", vec![Arg::Int(1)]));
+        let start = Instant::now();
+        while !state.hi_z[0] && start.elapsed() < Duration::from_secs(5) {
+            server.receive(&mut state, &device);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(state.mixer_entries[0].dests[0].gain, -10.0);
+        assert!(state.hi_z[0]);
+
+        // everything at first, then only what's changed
+        server.send(&state, &device);
+        let mut buf = [0; 1024];
+        let count = values(&state, &device).len();
+        for _ in 0..count {
+            client.recv(&mut buf).unwrap();
+        }
+        state.global_gain = -20.0;
+        server.send(&state, &device);
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let len = client.recv(&mut buf).unwrap();
+            received.extend(Message::decode(&buf[..len]));
+        }
+        assert_eq!(received, [
+            Message::new("/global/gain", vec![Arg::Float(-20.0)]),
+            Message::new("/global/fader", vec![Arg::Float(fader::position(-20.0))])
+        ]);
+    }
+}
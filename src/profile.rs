@@ -0,0 +1,410 @@
+// what's different between the interfaces in the Scarlett family, as far as the mixer is concerned
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Generation {
+    Gen1,
+    Gen2,
+    Gen3
+}
+
+// patterns for the alsa simple mixer names of each kind of control
+// {n} and {nn} are the (1-based) number, plain and two digits, {mix} is the mix bus ("Mix A"),
+// {name} the output's name, {side} "L" or "R", and {ch} the two digit channel of that side of an output
+pub struct ControlNames {
+    pub capture: &'static str,
+    pub matrix_input: &'static str,
+    pub matrix_gain: &'static str,
+    // software master volume, if there is one
+    pub global: Option<&'static str>,
+    // one stereo control per output, or one per channel if it has {side} or {ch}
+    pub output_gain: &'static str,
+    pub output_source: &'static str,
+    pub hi_z: &'static str,
+    pub pad: &'static str,
+    pub air: &'static str,
+    // label of the "on" item for switches that are enums rather than plain switches
+    pub hi_z_on: &'static str,
+    pub pad_on: &'static str,
+    // audio source labels, only used to simulate the driver
+    pub pcm: &'static str,
+    pub analog: &'static str,
+    pub spdif: &'static str,
+    pub adat: &'static str
+}
+
+// names used by the original (mixer_scarlett) driver
+pub const GEN1_NAMES: ControlNames = ControlNames {
+    capture: "Input Source {nn}",
+    matrix_input: "Matrix {nn} Input",
+    matrix_gain: "Matrix {nn} {mix}",
+    global: Some("Master"),
+    output_gain: "Master {n} ({name})",
+    output_source: "Master {n}{side} ({name}) Source",
+    hi_z: "Input {n} Impedance",
+    pad: "Input {n} Pad",
+    air: "Input {n} Air",
+    hi_z_on: "Hi-Z",
+    pad_on: "-10dB",
+    pcm: "PCM {n}",
+    analog: "Analog {n}",
+    spdif: "SPDIF {n}",
+    adat: "ADAT {n}"
+};
+
+// names used by the scarlett2 driver
+pub const GEN2_NAMES: ControlNames = ControlNames {
+    capture: "PCM {nn}",
+    matrix_input: "Mixer Input {nn}",
+    matrix_gain: "{mix} Input {nn}",
+    global: None,
+    output_gain: "Line {ch}",
+    output_source: "Analogue Output {ch}",
+    hi_z: "Line In {n} Level",
+    pad: "Line In {n} Pad",
+    air: "Line In {n} Air",
+    hi_z_on: "Inst",
+    pad_on: "On",
+    pcm: "PCM {n}",
+    analog: "Analogue {n}",
+    spdif: "S/PDIF {n}",
+    adat: "ADAT {n}"
+};
+
+pub struct DeviceProfile {
+    // shown in the ui
+    pub name: &'static str,
+    // name of the alsa card
+    pub card_name: &'static str,
+    pub generation: Generation,
+    pub names: &'static ControlNames,
+    // channels recorded by the computer
+    pub capture_channels: usize,
+    pub matrix_inputs: usize,
+    pub mix_buses: usize,
+    // stereo output pairs
+    pub outputs: &'static [&'static str],
+    // numbers of the inputs with each switch
+    pub hi_z: &'static [usize],
+    pub pad: &'static [usize],
+    pub air: &'static [usize],
+    // counts of each kind of audio source
+    pub pcm: usize,
+    pub analog: usize,
+    pub spdif: usize,
+    pub adat: usize
+}
+
+pub const PROFILES: &[DeviceProfile] = &[
+    DeviceProfile {
+        name: "Scarlett 6i6",
+        card_name: "Scarlett 6i6",
+        generation: Generation::Gen1,
+        names: &GEN1_NAMES,
+        capture_channels: 6,
+        matrix_inputs: 18,
+        mix_buses: 8,
+        outputs: &["Monitor", "Headphone", "SPDIF"],
+        hi_z: &[1, 2],
+        pad: &[1, 2],
+        air: &[],
+        pcm: 6, analog: 6, spdif: 2, adat: 0
+    },
+    DeviceProfile {
+        name: "Scarlett 8i6",
+        card_name: "Scarlett 8i6",
+        generation: Generation::Gen1,
+        names: &GEN1_NAMES,
+        capture_channels: 8,
+        matrix_inputs: 18,
+        mix_buses: 6,
+        outputs: &["Monitor", "Headphone", "SPDIF"],
+        hi_z: &[1, 2],
+        pad: &[3, 4],
+        air: &[],
+        pcm: 6, analog: 8, spdif: 2, adat: 0
+    },
+    DeviceProfile {
+        name: "Scarlett 18i6",
+        card_name: "Scarlett 18i6",
+        generation: Generation::Gen1,
+        names: &GEN1_NAMES,
+        capture_channels: 18,
+        matrix_inputs: 18,
+        mix_buses: 6,
+        outputs: &["Monitor", "Headphone", "SPDIF"],
+        hi_z: &[1, 2],
+        pad: &[],
+        air: &[],
+        pcm: 6, analog: 8, spdif: 2, adat: 8
+    },
+    DeviceProfile {
+        name: "Scarlett 18i8",
+        card_name: "Scarlett 18i8",
+        generation: Generation::Gen1,
+        names: &GEN1_NAMES,
+        capture_channels: 18,
+        matrix_inputs: 18,
+        mix_buses: 8,
+        outputs: &["Monitor", "Headphone 1", "Headphone 2", "SPDIF"],
+        hi_z: &[1, 2],
+        pad: &[1, 2, 3, 4],
+        air: &[],
+        pcm: 8, analog: 8, spdif: 2, adat: 8
+    },
+    DeviceProfile {
+        name: "Scarlett 18i20",
+        card_name: "Scarlett 18i20",
+        generation: Generation::Gen1,
+        names: &GEN1_NAMES,
+        capture_channels: 18,
+        matrix_inputs: 18,
+        mix_buses: 8,
+        outputs: &["Monitor", "Line 3/4", "Line 5/6", "Line 7/8", "Line 9/10", "SPDIF",
+            "ADAT 1/2", "ADAT 3/4", "ADAT 5/6", "ADAT 7/8"],
+        hi_z: &[],
+        pad: &[],
+        air: &[],
+        pcm: 20, analog: 8, spdif: 2, adat: 8
+    },
+    // gen 3 goes before gen 2 since they share card names, and only gen 3 has air
+    DeviceProfile {
+        name: "Scarlett 8i6 Gen 3",
+        card_name: "Scarlett 8i6 USB",
+        generation: Generation::Gen3,
+        names: &GEN2_NAMES,
+        capture_channels: 8,
+        matrix_inputs: 8,
+        mix_buses: 8,
+        outputs: &["Monitor", "Headphone"],
+        hi_z: &[1, 2],
+        pad: &[1, 2],
+        air: &[1, 2],
+        pcm: 6, analog: 6, spdif: 2, adat: 0
+    },
+    DeviceProfile {
+        name: "Scarlett 18i8 Gen 3",
+        card_name: "Scarlett 18i8 USB",
+        generation: Generation::Gen3,
+        names: &GEN2_NAMES,
+        capture_channels: 18,
+        matrix_inputs: 20,
+        mix_buses: 12,
+        outputs: &["Monitor", "Alt Monitor", "Headphone 1", "Headphone 2"],
+        hi_z: &[1, 2],
+        pad: &[1, 2, 3, 4],
+        air: &[1, 2, 3, 4],
+        pcm: 8, analog: 8, spdif: 2, adat: 8
+    },
+    DeviceProfile {
+        name: "Scarlett 18i20 Gen 3",
+        card_name: "Scarlett 18i20 USB",
+        generation: Generation::Gen3,
+        names: &GEN2_NAMES,
+        capture_channels: 20,
+        matrix_inputs: 25,
+        mix_buses: 12,
+        outputs: &["Monitor", "Line 3/4", "Line 5/6", "Line 7/8", "Line 9/10"],
+        hi_z: &[1, 2],
+        pad: &[1, 2, 3, 4, 5, 6, 7, 8],
+        air: &[1, 2, 3, 4, 5, 6, 7, 8],
+        pcm: 20, analog: 9, spdif: 2, adat: 8
+    },
+    DeviceProfile {
+        name: "Scarlett 6i6 Gen 2",
+        card_name: "Scarlett 6i6 USB",
+        generation: Generation::Gen2,
+        names: &GEN2_NAMES,
+        capture_channels: 6,
+        matrix_inputs: 18,
+        mix_buses: 10,
+        outputs: &["Monitor", "Line 3/4"],
+        hi_z: &[1, 2],
+        pad: &[1, 2],
+        air: &[],
+        pcm: 6, analog: 4, spdif: 2, adat: 0
+    },
+    DeviceProfile {
+        name: "Scarlett 18i8 Gen 2",
+        card_name: "Scarlett 18i8 USB",
+        generation: Generation::Gen2,
+        names: &GEN2_NAMES,
+        capture_channels: 18,
+        matrix_inputs: 18,
+        mix_buses: 8,
+        outputs: &["Monitor", "Headphone 1", "Headphone 2"],
+        hi_z: &[1, 2],
+        pad: &[1, 2, 3, 4],
+        air: &[],
+        pcm: 8, analog: 8, spdif: 2, adat: 8
+    },
+    DeviceProfile {
+        name: "Scarlett 18i20 Gen 2",
+        card_name: "Scarlett 18i20 USB",
+        generation: Generation::Gen2,
+        names: &GEN2_NAMES,
+        capture_channels: 20,
+        matrix_inputs: 18,
+        mix_buses: 10,
+        outputs: &["Monitor", "Line 3/4", "Line 5/6", "Line 7/8", "Line 9/10"],
+        hi_z: &[],
+        pad: &[],
+        air: &[],
+        pcm: 20, analog: 8, spdif: 2, adat: 8
+    }
+];
+
+// fill in a control name pattern
+fn fill(pattern: &str, vars: &[(&str, &str)]) -> String {
+    vars.iter().fold(pattern.to_owned(), |s, (k, v)| s.replace(k, v))
+}
+
+impl DeviceProfile {
+    // find the profile for a card, using its controls to tell apart generations that share a name
+    pub fn detect(card_name: &str, controls: &[String]) -> Option<&'static DeviceProfile> {
+        PROFILES.iter()
+            .filter(|p| p.card_name == card_name)
+            .find(|p| {
+                let signature = [p.capture(0), p.matrix_gain(0, &p.mix_bus(0))].into_iter()
+                    .chain(p.air.first().map(|n| p.air(*n)));
+                signature.into_iter().all(|c| controls.contains(&c))
+            })
+    }
+
+    // a profile by its display name
+    pub fn find(name: &str) -> Option<&'static DeviceProfile> {
+        PROFILES.iter().find(|p| p.name == name)
+    }
+
+    pub fn default_profile() -> &'static DeviceProfile {
+        DeviceProfile::find("Scarlett 18i6").unwrap()
+    }
+
+    pub fn mix_bus(&self, m: usize) -> String {
+        format!("Mix {}", (b'A' + m as u8) as char)
+    }
+
+    // `i` is 0-based from here on
+    pub fn capture(&self, i: usize) -> String {
+        fill(self.names.capture, &[("{nn}", &format!("{:02}", i + 1)), ("{n}", &(i + 1).to_string())])
+    }
+
+    pub fn matrix_input(&self, i: usize) -> String {
+        fill(self.names.matrix_input, &[("{nn}", &format!("{:02}", i + 1)), ("{n}", &(i + 1).to_string())])
+    }
+
+    pub fn matrix_gain(&self, i: usize, mix: &str) -> String {
+        fill(self.names.matrix_gain, &[("{nn}", &format!("{:02}", i + 1)), ("{n}", &(i + 1).to_string()), ("{mix}", mix)])
+    }
+
+    pub fn global(&self) -> Option<String> {
+        self.names.global.map(|g| g.to_owned())
+    }
+
+    fn output(&self, pattern: &str, i: usize, side: usize) -> String {
+        fill(pattern, &[
+            ("{n}", &(i + 1).to_string()),
+            ("{name}", self.outputs[i]),
+            ("{side}", ["L", "R"][side]),
+            ("{ch}", &format!("{:02}", i * 2 + side + 1))
+        ])
+    }
+
+    // the volume control(s) of an output, one for both channels or one each
+    pub fn output_gains(&self, i: usize) -> Vec<String> {
+        let per_channel = self.names.output_gain.contains("{side}") || self.names.output_gain.contains("{ch}");
+        (0..if per_channel { 2 } else { 1 }).map(|side| self.output(self.names.output_gain, i, side)).collect()
+    }
+
+    pub fn output_source(&self, i: usize, right: bool) -> String {
+        self.output(self.names.output_source, i, right as usize)
+    }
+
+    // `n` is the 1-based input number, as listed in `hi_z`, `pad` and `air`
+    pub fn hi_z(&self, n: usize) -> String {
+        fill(self.names.hi_z, &[("{n}", &n.to_string())])
+    }
+
+    pub fn pad(&self, n: usize) -> String {
+        fill(self.names.pad, &[("{n}", &n.to_string())])
+    }
+
+    pub fn air(&self, n: usize) -> String {
+        fill(self.names.air, &[("{n}", &n.to_string())])
+    }
+
+    // labels of every audio source in the order the driver lists them, without "Off"
+    pub fn source_labels(&self, mixes: bool) -> Vec<String> {
+        let numbered = |pattern: &str, count: usize| (1..=count)
+            .map(|n| fill(pattern, &[("{n}", &n.to_string())]))
+            .collect::<Vec<_>>();
+        let pcm = numbered(self.names.pcm, self.pcm);
+        let hardware = [
+            numbered(self.names.analog, self.analog),
+            numbered(self.names.spdif, self.spdif),
+            numbered(self.names.adat, self.adat)
+        ].concat();
+        let mixes = if mixes { (0..self.mix_buses).map(|m| self.mix_bus(m)).collect() } else { Vec::new() };
+        match self.generation {
+            Generation::Gen1 => [pcm, hardware, mixes].concat(),
+            Generation::Gen2 | Generation::Gen3 => [hardware, mixes, pcm].concat()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the simple mixer names of the controls the driver makes for `p`, as far as detecting it goes
+    fn controls(p: &DeviceProfile) -> Vec<String> {
+        let mut controls: Vec<String> = (0..p.capture_channels).map(|i| p.capture(i)).collect();
+        for i in 0..p.matrix_inputs {
+            controls.push(p.matrix_input(i));
+            controls.extend((0..p.mix_buses).map(|m| p.matrix_gain(i, &p.mix_bus(m))));
+        }
+        controls.extend(p.hi_z.iter().map(|n| p.hi_z(*n)));
+        controls.extend(p.pad.iter().map(|n| p.pad(*n)));
+        controls.extend(p.air.iter().map(|n| p.air(*n)));
+        controls
+    }
+
+    #[test]
+    fn tells_generations_with_the_same_card_name_apart() {
+        for (card, gen2, gen3) in [
+            ("Scarlett 18i8 USB", "Scarlett 18i8 Gen 2", "Scarlett 18i8 Gen 3"),
+            ("Scarlett 18i20 USB", "Scarlett 18i20 Gen 2", "Scarlett 18i20 Gen 3")
+        ] {
+            for name in [gen2, gen3] {
+                let p = DeviceProfile::find(name).unwrap();
+                assert_eq!(DeviceProfile::detect(card, &controls(p)).map(|p| p.name), Some(name));
+            }
+        }
+        // and every other profile is found from its own controls, whatever order they're listed in
+        for p in PROFILES {
+            assert_eq!(DeviceProfile::detect(p.card_name, &controls(p)).map(|p| p.name), Some(p.name));
+        }
+        assert!(DeviceProfile::detect("Scarlett 18i8 USB", &[]).is_none());
+    }
+
+    #[test]
+    fn names_match_the_drivers() {
+        let gen1 = DeviceProfile::find("Scarlett 18i8").unwrap();
+        assert_eq!(gen1.output_gains(0), ["Master 1 (Monitor)"]);
+        assert_eq!(gen1.output_source(1, true), "Master 2R (Headphone 1) Source");
+        assert_eq!(gen1.capture(0), "Input Source 01");
+        assert_eq!(gen1.matrix_input(9), "Matrix 10 Input");
+        assert_eq!(gen1.matrix_gain(0, &gen1.mix_bus(1)), "Matrix 01 Mix B");
+        assert_eq!(gen1.hi_z(1), "Input 1 Impedance");
+        assert_eq!(gen1.global().as_deref(), Some("Master"));
+
+        let gen2 = DeviceProfile::find("Scarlett 18i20 Gen 2").unwrap();
+        assert_eq!(gen2.output_gains(1), ["Line 03", "Line 04"]);
+        assert_eq!(gen2.output_source(0, true), "Analogue Output 02");
+        assert_eq!(gen2.capture(11), "PCM 12");
+        assert_eq!(gen2.matrix_input(0), "Mixer Input 01");
+        assert_eq!(gen2.matrix_gain(17, &gen2.mix_bus(9)), "Mix J Input 18");
+        assert_eq!(gen2.global(), None);
+        let gen3 = DeviceProfile::find("Scarlett 18i8 Gen 3").unwrap();
+        assert_eq!((gen3.hi_z(2), gen3.air(4)), ("Line In 2 Level".to_owned(), "Line In 4 Air".to_owned()));
+    }
+}
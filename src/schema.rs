@@ -0,0 +1,263 @@
+// saved state carries a version, and anything older is upgraded one step at a time when it's loaded
+//
+// the state and scenes in eframe's storage are saved as `(version: 2, data: ...)`. things saved before
+// there were versions are recognised by their shape. each version of the state:
+//
+//   0: the 18i6 only, with its two hi-z switches as hi_z_1 and hi_z_2
+//   1: hi_z, pad and air lists for any model, with ports as indices into the device's lists
+//   2: ports by name
+//
+// exported files have a `version` of their own:
+//
+//   0: before files had a version
+//   1: the same, with `version`
+
+use std::collections::HashMap;
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{app::{AppState, APP_ID}, device::Device, state::{Port, Scene}};
+
+pub const VERSION: u32 = 2;
+pub const FILE_VERSION: u32 = 1;
+
+type Object = Map<String, Value>;
+
+// STEPS[n] upgrades a state from version n to n + 1
+const STEPS: [fn(&mut Object, &Device); VERSION as usize] = [split_hi_z, name_ports];
+const FILE_STEPS: [fn(&mut Object); FILE_VERSION as usize] = [add_version];
+
+#[derive(Serialize)]
+struct Versioned<T> {
+    version: u32,
+    data: T
+}
+
+pub fn store<T: Serialize>(storage: &mut dyn eframe::Storage, key: &str, data: &T) {
+    eframe::set_value(storage, key, &Versioned { version: VERSION, data });
+}
+
+// the state saved under `key` at the current version, and the version it was saved as
+pub fn load_state(storage: &dyn eframe::Storage, key: &str, device: &Device) -> Option<(AppState, u32)> {
+    load(storage, key, |state, version| upgrade_state(state, version, device))
+}
+
+pub fn load_scenes(storage: &dyn eframe::Storage, key: &str, device: &Device) -> Option<(Vec<Scene>, u32)> {
+    load(storage, key, |scenes, version| {
+        let states = scenes.as_array_mut().into_iter()
+            .flatten()
+            .filter_map(|s| s.get_mut("state"));
+        // before versions, each scene was saved as whatever the state was at the time
+        states.map(|s| upgrade_state(s, version, device)).min().unwrap_or(VERSION)
+    })
+}
+
+// copy eframe's storage aside before something upgraded from `version` is saved over it.
+// the oldest copy of each version is kept
+pub fn backup_storage(version: u32) {
+    let Some(dir) = eframe::storage_dir(APP_ID) else {
+        return;
+    };
+    let backup = dir.join(format!("app.ron.v{version}.bak"));
+    if !backup.exists() {
+        if let Err(e) = std::fs::copy(dir.join("app.ron"), &backup) {
+            log::warn!("couldn't back up saved state to {}: {}", backup.display(), e);
+        }
+    }
+}
+
+// eframe's storage read straight from its file, for looking at what the app saved without running it
+#[derive(Default)]
+pub struct SavedStorage(HashMap<String, String>);
+
+impl SavedStorage {
+    pub fn open() -> Result<SavedStorage, String> {
+        let path = eframe::storage_dir(APP_ID).ok_or("there's nowhere for saved settings")?.join("app.ron");
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        ron::from_str(&text).map(SavedStorage).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl eframe::Storage for SavedStorage {
+    fn get_string(&self, key: &str) -> Option<String> {
+        self.0.get(key).cloned()
+    }
+
+    fn set_string(&mut self, key: &str, value: String) {
+        self.0.insert(key.to_owned(), value);
+    }
+
+    fn flush(&mut self) {}
+}
+
+// upgrade an exported file to the current version, returning the version it was at
+pub fn upgrade_file(file: &mut Value) -> u32 {
+    let Value::Object(file) = file else {
+        return FILE_VERSION;
+    };
+    let version = file.get("version").and_then(Value::as_u64).map_or(0, |v| v as u32);
+    for step in FILE_STEPS.iter().skip(version as usize) {
+        step(file);
+    }
+    version
+}
+
+// `upgrade` brings the data up to date from the version it was saved with (if it was) and returns the
+// version it was at
+fn load<T: DeserializeOwned>(
+    storage: &dyn eframe::Storage,
+    key: &str,
+    upgrade: impl FnOnce(&mut Value, Option<u32>) -> u32
+) -> Option<(T, u32)> {
+    // going through a Value rather than straight to T, so older shapes can be fixed up first.
+    // ron's own, since serde_json's can't be deserialized from ron structs
+    let saved: ron::Value = eframe::get_value(storage, key)?;
+    let saved: Value = saved.into_rust().ok()?;
+    let (version, mut data) = match saved {
+        Value::Object(mut m) if m.len() == 2 && m.contains_key("version") && m.contains_key("data") => (
+            m.get("version").and_then(Value::as_u64).map(|v| v as u32),
+            m.remove("data").unwrap()
+        ),
+        data => (None, data)
+    };
+    let version = upgrade(&mut data, version);
+    if version > VERSION {
+        log::warn!("{key} was saved by a newer version, anything it doesn't know about will be lost");
+    }
+    match serde_json::from_value(data) {
+        Ok(data) => Some((data, version)),
+        Err(e) => {
+            log::error!("couldn't load {key}: {e}");
+            None
+        }
+    }
+}
+
+fn upgrade_state(state: &mut Value, version: Option<u32>, device: &Device) -> u32 {
+    let version = version.unwrap_or_else(|| guess_version(state));
+    if let Value::Object(state) = state {
+        for step in STEPS.iter().skip(version as usize) {
+            step(state, device);
+        }
+    }
+    version
+}
+
+fn guess_version(state: &Value) -> u32 {
+    if state.get("hi_z_1").is_some() || state.get("hi_z_2").is_some() {
+        return 0;
+    }
+    let indexed = ["/outputs/0/source/0", "/mixer_entries/0/source"].iter()
+        .filter_map(|p| state.pointer(p))
+        .chain(state["capture"].as_array().into_iter().flatten())
+        .any(Value::is_u64);
+    if indexed { 1 } else { 2 }
+}
+
+// 0 -> 1: the 18i6's hi-z inputs are 1 and 2
+fn split_hi_z(state: &mut Object, _: &Device) {
+    let hi_z = ["hi_z_1", "hi_z_2"].map(|k| state.remove(k).unwrap_or(Value::Bool(false)));
+    state.insert("hi_z".to_owned(), Value::Array(hi_z.into()));
+}
+
+// 1 -> 2: name ports after what they are on `device`, which is hopefully the one the state was saved with
+fn name_ports(state: &mut Object, device: &Device) {
+    fn name(port: &mut Value, labels: &[String]) {
+        if let Some(i) = port.as_u64() {
+            *port = Value::String(Port::at(labels, i as usize).0);
+        }
+    }
+    fn each(v: Option<&mut Value>) -> impl Iterator<Item = &mut Value> {
+        v.and_then(Value::as_array_mut).into_iter().flatten()
+    }
+    let sources = &device.audio_sources;
+
+    for c in each(state.get_mut("capture")) {
+        name(c, sources);
+    }
+    for e in each(state.get_mut("mixer_entries")) {
+        for k in ["source", "source_r"] {
+            if let Some(p) = e.get_mut(k) {
+                name(p, sources);
+            }
+        }
+        for d in each(e.get_mut("dests")) {
+            for k in ["dest", "dest_r"] {
+                if let Some(p) = d.get_mut(k) {
+                    name(p, &device.mixer_destinations);
+                }
+            }
+        }
+    }
+    for o in each(state.get_mut("outputs")) {
+        for p in each(o.get_mut("source")) {
+            name(p, sources);
+        }
+    }
+}
+
+// 0 -> 1: nothing else changed
+fn add_version(_: &mut Object) {}
+
+#[cfg(test)]
+mod tests {
+    use eframe::Storage;
+
+    use crate::profile::DeviceProfile;
+
+    use super::*;
+
+    fn device() -> Device {
+        Device::simulated(DeviceProfile::find("Scarlett 18i6").unwrap())
+    }
+
+    #[test]
+    fn upgrades_the_first_version() {
+        let device = device();
+        let mut storage = SavedStorage::default();
+        // as the first release saved it
+        storage.set_string(eframe::APP_KEY, r#"(
+            capture: [Some(1), None],
+            mixer_entries: [(name: "Guitar", enabled: true, stereo: false, split: false, source: 2, source_r: 3,
+                dests: [(stereo: true, dest: 0, dest_r: 1, split: false, gain: -6.0)])],
+            global_gain: -10.0,
+            global_mute: false,
+            hi_z_1: false,
+            hi_z_2: true,
+            outputs: (
+                (name: "Monitor", gain: 0.0, mute: false, source: (1, 2), split: false),
+                (name: "Headphones 1", gain: 0.0, mute: false, source: (1, 2), split: false),
+                (name: "Headphones 2", gain: 0.0, mute: false, source: (1, 2), split: false),
+            ),
+        )"#.to_owned());
+
+        let (state, version) = load_state(&storage, eframe::APP_KEY, &device).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(state.hi_z, [false, true]);
+        assert_eq!(state.global_gain, -10.0);
+        assert_eq!(state.capture[0], Some(Port::at(&device.audio_sources, 1)));
+        let e = &state.mixer_entries[0];
+        assert_eq!(e.source, Port::at(&device.audio_sources, 2));
+        assert_eq!(e.dests[0].dest_r, Port::at(&device.mixer_destinations, 1));
+        assert_eq!(state.outputs[0].source.1, Port::at(&device.audio_sources, 2));
+    }
+
+    #[test]
+    fn loads_what_it_stores() {
+        let device = device();
+        let mut storage = SavedStorage::default();
+        let mut state = device.known().to_state(&device);
+        state.global_gain = -3.0;
+        store(&mut storage, "state", &state);
+        store(&mut storage, "scenes", &vec![Scene { name: "Live".to_owned(), state: state.clone() }]);
+
+        let (loaded, version) = load_state(&storage, "state", &device).unwrap();
+        assert_eq!(version, VERSION);
+        assert_eq!(loaded.global_gain, -3.0);
+        assert_eq!(loaded.outputs[0].source, state.outputs[0].source);
+        let (scenes, version) = load_scenes(&storage, "scenes", &device).unwrap();
+        assert_eq!(version, VERSION);
+        assert_eq!(scenes[0].state.capture, state.capture);
+    }
+}
@@ -0,0 +1,291 @@
+// sessions exported to a file, for keeping in version control or moving to another machine
+//
+// sources, mixes and outputs are written by name rather than by their index on one interface,
+// so files can be edited by hand and mostly survive being loaded on a different model. eg. in toml:
+//
+//     version = 1
+//     device = "Scarlett 18i6"
+//     capture = ["Analog 1", "Analog 2", "Off"]
+//     global_gain = 0.0
+//     global_mute = false
+//     hi_z = [false, true]
+//     pad = [false, false]
+//     air = []
+//     pan_law = -3.0
+//
+//     [[mixer]]
+//     name = "Guitar"
+//     enabled = true
+//     mute = false
+//     solo = false
+//     stereo = false
+//     split = false
+//     source = "Analog 2"
+//     source_r = "Analog 3"
+//
+//     [[mixer.dests]]
+//     stereo = true
+//     split = false
+//     dest = "Mix A"
+//     dest_r = "Mix B"
+//     gain = -6.0
+//     mute = false
+//     pan = 0.0
+//     solo = false
+//
+//     [[outputs]]
+//     name = "Monitor"
+//     gain = -20.0
+//     mute = false
+//     split = false
+//     source = "Mix A"
+//     source_r = "Mix B"
+//
+// "Off" turns a capture channel off. files ending in .json hold the same thing as json. files from
+// older versions are upgraded when they're loaded, after copying the original to eg. "session.toml.v0.bak"
+
+use std::path::Path;
+
+use crate::{app::AppState, device::Device, schema::{self, FILE_VERSION}, state::{new_id, MixerDestination, MixerEntry, MixerOutput, PanLaw, Port}};
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct SessionFile {
+    #[serde(default)]
+    pub version: u32,
+    // the model it was exported from, just for reference
+    #[serde(default)]
+    pub device: String,
+    #[serde(default)]
+    pub capture: Vec<String>,
+    #[serde(default)]
+    pub global_gain: f32,
+    #[serde(default)]
+    pub global_mute: bool,
+    #[serde(default)]
+    pub hi_z: Vec<bool>,
+    #[serde(default)]
+    pub pad: Vec<bool>,
+    #[serde(default)]
+    pub air: Vec<bool>,
+    #[serde(default)]
+    pub pan_law: PanLaw,
+    #[serde(default)]
+    pub mixer: Vec<EntryFile>,
+    #[serde(default)]
+    pub outputs: Vec<OutputFile>
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct EntryFile {
+    pub name: String,
+    pub enabled: bool,
+    #[serde(default)]
+    pub mute: bool,
+    #[serde(default)]
+    pub solo: bool,
+    pub stereo: bool,
+    pub split: bool,
+    pub source: String,
+    pub source_r: String,
+    pub dests: Vec<DestinationFile>
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct DestinationFile {
+    pub stereo: bool,
+    pub split: bool,
+    pub dest: String,
+    pub dest_r: String,
+    pub gain: f32,
+    #[serde(default)]
+    pub mute: bool,
+    #[serde(default)]
+    pub pan: f32,
+    #[serde(default)]
+    pub solo: bool
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct OutputFile {
+    pub name: String,
+    pub gain: f32,
+    pub mute: bool,
+    pub split: bool,
+    pub source: String,
+    pub source_r: String
+}
+
+impl SessionFile {
+    pub fn from_state(s: &AppState, device: &Device) -> SessionFile {
+        SessionFile {
+            version: FILE_VERSION,
+            device: device.profile.name.to_owned(),
+            capture: s.capture.iter().map(|c| c.as_ref().map_or("Off", |p| p.0.as_str()).to_owned()).collect(),
+            global_gain: s.global_gain,
+            global_mute: s.global_mute,
+            hi_z: s.hi_z.clone(),
+            pad: s.pad.clone(),
+            air: s.air.clone(),
+            pan_law: s.pan_law,
+            mixer: s.mixer_entries.iter().map(|e| EntryFile {
+                name: e.name.clone(),
+                enabled: e.enabled,
+                mute: e.mute,
+                solo: e.solo,
+                stereo: e.stereo,
+                split: e.split,
+                source: e.source.0.clone(),
+                source_r: e.source_r.0.clone(),
+                dests: e.dests.iter().map(|d| DestinationFile {
+                    stereo: d.stereo,
+                    split: d.split,
+                    dest: d.dest.0.clone(),
+                    dest_r: d.dest_r.0.clone(),
+                    gain: d.gain,
+                    mute: d.mute,
+                    pan: d.pan,
+                    solo: d.solo
+                }).collect()
+            }).collect(),
+            outputs: s.outputs.iter().map(|o| OutputFile {
+                name: o.name.clone(),
+                gain: o.gain,
+                mute: o.mute,
+                split: o.split,
+                source: o.source.0.0.clone(),
+                source_r: o.source.1.0.clone()
+            }).collect()
+        }
+    }
+
+    // the state this describes on `device`, and any names the device doesn't have
+    pub fn to_state(&self, device: &Device) -> (AppState, Vec<String>) {
+        let mut s = AppState {
+            capture: self.capture.iter()
+                .map(|c| (c != "Off").then(|| Port::new(c)))
+                .collect(),
+            global_gain: self.global_gain,
+            global_mute: self.global_mute,
+            hi_z: self.hi_z.clone(),
+            pad: self.pad.clone(),
+            air: self.air.clone(),
+            pan_law: self.pan_law,
+            mixer_entries: self.mixer.iter().map(|e| MixerEntry {
+                name: e.name.clone(),
+                enabled: e.enabled,
+                mute: e.mute,
+                solo: e.solo,
+                stereo: e.stereo,
+                split: e.split,
+                source: Port::new(&e.source),
+                source_r: Port::new(&e.source_r),
+                dests: e.dests.iter().map(|d| MixerDestination {
+                    stereo: d.stereo,
+                    split: d.split,
+                    dest: Port::new(&d.dest),
+                    dest_r: Port::new(&d.dest_r),
+                    gain: d.gain,
+                    mute: d.mute,
+                    pan: d.pan,
+                    solo: d.solo,
+                    id: new_id()
+                }).collect(),
+                id: new_id()
+            }).collect(),
+            outputs: Vec::new()
+        };
+        s.fit(device);
+        let mut missing = Vec::new();
+        // outputs go by name, since different models have different ones
+        for o in &self.outputs {
+            let Some(i) = s.outputs.iter().position(|so| so.name == o.name) else {
+                missing.push(o.name.clone());
+                continue;
+            };
+            s.outputs[i] = MixerOutput {
+                name: o.name.clone(),
+                gain: o.gain,
+                mute: o.mute,
+                split: o.split,
+                source: (Port::new(&o.source), Port::new(&o.source_r))
+            };
+        }
+        missing.extend(s.unresolved(device));
+        (s, missing)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = self.to_text(is_json(path))?;
+        std::fs::write(path, text).map_err(|e| e.to_string())
+    }
+
+    // as json, or as toml otherwise
+    pub fn to_text(&self, json: bool) -> Result<String, String> {
+        if json {
+            serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+        } else {
+            toml::to_string_pretty(self).map_err(|e| e.to_string())
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<SessionFile, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut value: serde_json::Value = if is_json(path) {
+            serde_json::from_str(&text).map_err(|e| e.to_string())?
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())?
+        };
+        let version = schema::upgrade_file(&mut value);
+        let file: SessionFile = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        if version < FILE_VERSION {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(format!(".v{version}.bak"));
+            std::fs::copy(path, &backup).map_err(|e| e.to_string())?;
+            file.save(path)?;
+        }
+        Ok(file)
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profile::DeviceProfile;
+
+    use super::*;
+
+    fn guitar(from: &Device) -> SessionFile {
+        let mut state = from.known().to_state(from);
+        let mut entry = MixerEntry::new(from);
+        entry.name = "Guitar".to_owned();
+        entry.source = Port::new("Analog 2");
+        state.mixer_entries = vec![entry];
+
+        let text = toml::to_string_pretty(&SessionFile::from_state(&state, from)).unwrap();
+        toml::from_str(&text).unwrap()
+    }
+
+    #[test]
+    fn round_trips_across_models() {
+        let from = Device::simulated(DeviceProfile::find("Scarlett 18i20").unwrap());
+        let to = Device::simulated(DeviceProfile::find("Scarlett 18i8").unwrap());
+        let (loaded, missing) = guitar(&from).to_state(&to);
+        let e = &loaded.mixer_entries[0];
+        assert_eq!(e.name, "Guitar");
+        assert_eq!(e.source, Port::new("Analog 2"));
+        assert_eq!(e.dests[0].dest, Port::new("Mix A"));
+        assert!(!missing.contains(&"Analog 2".to_owned()));
+    }
+
+    #[test]
+    fn reports_missing_names() {
+        let from = Device::simulated(DeviceProfile::find("Scarlett 18i20").unwrap());
+        let to = Device::simulated(DeviceProfile::find("Scarlett 18i20 Gen 2").unwrap());
+        let (_, missing) = guitar(&from).to_state(&to);
+        // gen 2 calls them "Analogue"
+        assert!(missing.contains(&"Analog 2".to_owned()));
+    }
+}
@@ -1,4 +1,37 @@
-use crate::device::{Device, EnumIndex};
+use std::hash::{BuildHasher, Hasher};
+
+use crate::{app::AppState, device::{Device, EnumIndex}};
+
+// tells entries (or an entry's destinations) apart, wherever they are in the list, so midi mappings
+// stay with them when others are added or removed
+pub type Id = u64;
+
+// random, so ones made by different runs of the app don't clash
+pub fn new_id() -> Id {
+    std::collections::hash_map::RandomState::new().build_hasher().finish()
+}
+
+// an audio source or mix by the name the driver gives it, which (unlike its place in the driver's list)
+// stays the same across kernel versions and models
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[serde(transparent)]
+pub struct Port(pub String);
+
+impl Port {
+    pub fn new(name: &str) -> Self {
+        Port(name.to_owned())
+    }
+
+    // the port at `i` in `labels`, or an empty one that won't resolve
+    pub fn at(labels: &[String], i: EnumIndex) -> Self {
+        Port(labels.get(i).cloned().unwrap_or_default())
+    }
+
+    // where the port is in `labels`, if the device has it
+    pub fn resolve(&self, labels: &[String]) -> Option<EnumIndex> {
+        labels.iter().position(|l| *l == self.0)
+    }
+}
 
 /*#[derive(serde::Serialize, serde::Deserialize, strum_macros::Display, strum_macros::VariantArray, PartialEq, Copy, Clone)]
 pub enum AudioSource {
@@ -80,24 +113,109 @@ pub enum AudioDestination {
     MixF
 }*/
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
 pub struct MixerDestination {
     pub stereo: bool,
-    pub dest: EnumIndex,
-    pub dest_r: EnumIndex,
+    pub dest: Port,
+    pub dest_r: Port,
     pub split: bool,
-    pub gain: f32
+    pub gain: f32,
+    // keeps the gain while it's muted, see `device::audible`
+    #[serde(default)]
+    pub mute: bool,
+    // -1 (left) to 1 (right). pans a mono source, or balances a stereo one, across a stereo destination
+    #[serde(default)]
+    pub pan: f32,
+    #[serde(default)]
+    pub solo: bool,
+    // saved states from before there were ids get new ones
+    #[serde(default = "new_id")]
+    pub id: Id
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+impl MixerDestination {
+    // dB added to the gain into the left and right mixes for the pan (from a mono source) or balance
+    // (from a stereo one). both are 0dB in the middle, so the level there is the gain
+    pub fn pan_offsets(&self, source_stereo: bool, law: PanLaw) -> [f32; 2] {
+        let pan = self.pan.clamp(-1.0, 1.0);
+        let db = |amplitude: f32| 20.0 * amplitude.log10();
+        match (source_stereo, self.stereo) {
+            // turning one side down, the other staying where it is
+            (true, true) => [db(1.0 - pan.max(0.0)), db(1.0 + pan.min(0.0))],
+            // the law's the level in the middle relative to panned hard to one side, and the curve
+            // between is a power of the constant power one that gives it
+            (false, true) => {
+                let power = law.db() / db(std::f32::consts::FRAC_1_SQRT_2);
+                let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+                [db(angle.cos()), db(angle.sin())].map(|side| power * side - law.db())
+            }
+            (_, false) => [0.0, 0.0]
+        }
+    }
+}
+
+// how much quieter a mono source panned to the middle is in each side than panned hard to that side.
+// saved as that many dB
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(try_from = "f32", into = "f32")]
+pub enum PanLaw {
+    #[default]
+    Minus3,
+    Minus4_5,
+    Minus6
+}
+
+impl PanLaw {
+    pub const ALL: [PanLaw; 3] = [PanLaw::Minus3, PanLaw::Minus4_5, PanLaw::Minus6];
+
+    pub fn db(self) -> f32 {
+        match self {
+            PanLaw::Minus3 => -3.0,
+            PanLaw::Minus4_5 => -4.5,
+            PanLaw::Minus6 => -6.0
+        }
+    }
+}
+
+impl From<PanLaw> for f32 {
+    fn from(law: PanLaw) -> f32 {
+        law.db()
+    }
+}
+
+impl TryFrom<f32> for PanLaw {
+    type Error = String;
+
+    fn try_from(db: f32) -> Result<Self, Self::Error> {
+        PanLaw::ALL.into_iter()
+            .find(|law| law.db() == db)
+            .ok_or_else(|| format!("{db}dB isn't a pan law, it can be -3, -4.5 or -6"))
+    }
+}
+
+impl std::fmt::Display for PanLaw {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}dB", self.db())
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
 pub struct MixerEntry {
     pub name: String,
+    // disabled entries don't take up matrix inputs, muted ones still do
     pub enabled: bool,
+    #[serde(default)]
+    pub mute: bool,
+    // soloing an entry solos all of its destinations
+    #[serde(default)]
+    pub solo: bool,
     pub stereo: bool,
     pub split: bool,
-    pub source: EnumIndex,
-    pub source_r: EnumIndex,
-    pub dests: Vec<MixerDestination>
+    pub source: Port,
+    pub source_r: Port,
+    pub dests: Vec<MixerDestination>,
+    #[serde(default = "new_id")]
+    pub id: Id
 }
 
 // given a slice of true and false values, find the index of the start of a pair of false values
@@ -115,22 +233,26 @@ impl MixerEntry {
         let mut e = Self {
             name: "Unnamed".to_owned(),
             enabled: true,
+            mute: false,
+            solo: false,
             stereo: false,
             split: false,
-            source: 0/*AudioSource::Analog1*/,
-            source_r: 1/*AudioSource::Analog2*/,
-            dests: Vec::new()
+            source: Port::at(&device.audio_sources, 0)/*AudioSource::Analog1*/,
+            source_r: Port::at(&device.audio_sources, 1)/*AudioSource::Analog2*/,
+            dests: Vec::new(),
+            id: new_id()
         };
         e.add_dest(device);
         e
     }
 
     pub fn add_dest(&mut self, device: &Device) {
-        let mut used = [false; 6];
+        let mut used = vec![false; device.mixer_destinations.len()];
         self.dests.iter()
-            .flat_map(|d| if d.stereo { vec![ d.dest, d.dest_r ] } else { vec![ d.dest ]})
+            .flat_map(|d| if d.stereo { vec![ &d.dest, &d.dest_r ] } else { vec![ &d.dest ]})
+            .filter_map(|d| d.resolve(&device.mixer_destinations))
             // .map(|d| AudioDestination::VARIANTS.iter().position(|v| *v == d).unwrap())
-            .for_each(|cur| { used[cur] = true; });
+            .for_each(|cur| if let Some(u) = used.get_mut(cur) { *u = true; });
 
         let stereo = self.stereo;
         let available = if stereo {
@@ -143,27 +265,86 @@ impl MixerEntry {
         // mono source to stereo dest = route source to dest l and dest r
         // mono src -> mono dest, stereo src -> stereo dest = self explanatory
 
+        let dest = available.unwrap_or(0 /*AudioDestination::MixA, |i| AudioDestination::VARIANTS[i]*/);
+        let dest_r = available.map_or(1 /*AudioDestination::MixB*/, |i| if i + 1 < /*AudioDestination::VARIANTS*/device.mixer_destinations.len() {
+            // AudioDestination::VARIANTS[i + 1]
+            i + 1
+        } else {
+            // AudioDestination::MixF
+            device.mixer_destinations.len() - 1
+        });
         self.dests.push(MixerDestination {
             gain: 0.0,
             stereo,
-            dest: available.unwrap_or(0 /*AudioDestination::MixA, |i| AudioDestination::VARIANTS[i]*/),
-            dest_r: available.map_or(1 /*AudioDestination::MixB*/, |i| if i + 1 < /*AudioDestination::VARIANTS*/device.mixer_destinations.len() {
-                // AudioDestination::VARIANTS[i + 1]
-                i + 1
-            } else {
-                // AudioDestination::MixF
-                device.mixer_destinations.len() - 1
-            }),
-            split: false
+            dest: Port::at(&device.mixer_destinations, dest),
+            dest_r: Port::at(&device.mixer_destinations, dest_r),
+            split: false,
+            mute: false,
+            pan: 0.0,
+            solo: false,
+            id: new_id()
         });
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, PartialEq)]
 pub struct MixerOutput {
     pub name: String,
     pub gain: f32,
     pub mute: bool,
-    pub source: (EnumIndex, EnumIndex),
+    pub source: (Port, Port),
     pub split: bool
-}
\ No newline at end of file
+}
+
+// a named snapshot of the whole mixer, eg. "Tracking"
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq)]
+pub struct Scene {
+    pub name: String,
+    pub state: AppState
+}
+
+impl Scene {
+    // `base`, or `base` with a number after it if a scene's already called that
+    pub fn unique_name(scenes: &[Scene], base: &str) -> String {
+        (1..).map(|n| if n == 1 { base.to_owned() } else { format!("{} {}", base, n) })
+            .find(|name| !scenes.iter().any(|s| s.name == *name))
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dest(stereo: bool, pan: f32) -> MixerDestination {
+        MixerDestination {
+            stereo,
+            dest: Port::new("Mix A"),
+            dest_r: Port::new("Mix B"),
+            split: false,
+            gain: 0.0,
+            mute: false,
+            pan,
+            solo: false,
+            id: 0
+        }
+    }
+
+    fn near(a: [f32; 2], b: [f32; 2]) -> bool {
+        a.iter().zip(b).all(|(a, b)| a == &b || (a - b).abs() < 0.01)
+    }
+
+    #[test]
+    fn pans_follow_the_law() {
+        for law in PanLaw::ALL {
+            assert!(near(dest(true, 0.0).pan_offsets(false, law), [0.0, 0.0]));
+            // hard left is as much louder on the left as the law says the middle is quieter
+            assert!(near(dest(true, -1.0).pan_offsets(false, law), [-law.db(), f32::NEG_INFINITY]));
+        }
+        let [l, r] = dest(true, 0.5).pan_offsets(false, PanLaw::Minus3);
+        assert!(l < 0.0 && r > 0.0 && r < 3.0);
+        // balance only ever turns a side down
+        assert!(near(dest(true, 0.5).pan_offsets(true, PanLaw::Minus6), [-6.02, 0.0]));
+        assert!(near(dest(false, 0.5).pan_offsets(false, PanLaw::Minus3), [0.0, 0.0]));
+    }
+}
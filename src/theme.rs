@@ -9,6 +9,10 @@ pub mod colors {
     pub const BG2: egui::Color32 = egui::Color32::from_rgb(55, 55, 69);
     pub const ON: egui::Color32 = egui::Color32::from_rgb(178, 121, 242);
     pub const ACTIVE: egui::Color32 = egui::Color32::from_rgb(113, 58, 145);
+    pub const METER: egui::Color32 = egui::Color32::from_rgb(98, 196, 128);
+    pub const METER_WARM: egui::Color32 = egui::Color32::from_rgb(226, 196, 92);
+    pub const METER_HOT: egui::Color32 = egui::Color32::from_rgb(232, 132, 72);
+    pub const CLIP: egui::Color32 = egui::Color32::from_rgb(232, 72, 72);
 
 }
 
@@ -33,7 +37,7 @@ pub fn font() -> epaint::text::FontInsert {
 
     epaint::text::FontInsert {
         name: MY_FONT.to_owned(),
-        data: egui::FontData::from_static(include_bytes!("../KosugiMaruModded-Regular.ttf")).into(),
+        data: egui::FontData::from_static(include_bytes!("../KosugiMaruModded-Regular.ttf")),
         families: vec![
             epaint::text::InsertFontFamily {
                 family: egui::FontFamily::Proportional,
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, sync::{mpsc, Arc, Condvar, Mutex}, thread::JoinHandle, time::{Duration, Instant}};
+
+use crate::{backend::MixerBackend, device::{DeviceError, ElemValue}};
+
+// fastest any one control gets written, so dragging a gain doesn't flood the usb control endpoint
+const MIN_INTERVAL: Duration = Duration::from_millis(25);
+
+// what a control ended up set to after a write, or why it couldn't be
+pub type WriteResult = (String, Result<ElemValue, DeviceError>);
+
+struct Queue {
+    // only the latest value for each control, older ones are never written
+    values: HashMap<String, ElemValue>,
+    // write whatever's left and stop
+    stopped: bool,
+    notify: Option<Box<dyn Fn() + Send>>
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    wake: Condvar
+}
+
+// writes controls on a background thread, so the ui never waits on the hardware
+pub struct WriteQueue {
+    shared: Arc<Shared>,
+    results: mpsc::Receiver<WriteResult>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl WriteQueue {
+    // `backend` is the writer's own handle, see `MixerBackend::writer`
+    pub fn new(mut backend: Box<dyn MixerBackend>) -> WriteQueue {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                values: HashMap::new(),
+                stopped: false,
+                notify: None
+            }),
+            wake: Condvar::new()
+        });
+        let (tx, results) = mpsc::channel();
+        let thread = {
+            let shared = shared.clone();
+            std::thread::spawn(move || run(&shared, &mut *backend, &tx))
+        };
+        WriteQueue {
+            shared,
+            results,
+            thread: Some(thread)
+        }
+    }
+
+    // call `notify` from the background thread when there are results to pick up
+    pub fn watch(&self, notify: Box<dyn Fn() + Send>) {
+        self.shared.queue.lock().unwrap().notify = Some(notify);
+    }
+
+    // write `value` to `control` soon, replacing anything still waiting to be written to it
+    pub fn push(&self, control: String, value: ElemValue) {
+        self.shared.queue.lock().unwrap().values.insert(control, value);
+        self.shared.wake.notify_one();
+    }
+
+    // results of the writes that have finished since the last call
+    pub fn results(&self) -> Vec<WriteResult> {
+        self.results.try_iter().collect()
+    }
+
+    // write everything queued and wait for it. nothing pushed after this is written
+    pub fn flush(&mut self) {
+        self.shared.queue.lock().unwrap().stopped = true;
+        self.shared.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for WriteQueue {
+    // finish off pending writes, so nothing's lost when switching devices or quitting
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn run(shared: &Shared, backend: &mut dyn MixerBackend, tx: &mpsc::Sender<WriteResult>) {
+    // when each control was last written
+    let mut last: HashMap<String, Instant> = HashMap::new();
+    let mut queue = shared.queue.lock().unwrap();
+    loop {
+        let stopping = queue.stopped;
+        if stopping && queue.values.is_empty() {
+            break;
+        }
+
+        let now = Instant::now();
+        let wait = |k: &String| last.get(k).map_or(Duration::ZERO, |t| MIN_INTERVAL.saturating_sub(now - *t));
+        let due: Vec<String> = queue.values.keys()
+            .filter(|k| stopping || wait(k).is_zero())
+            .cloned()
+            .collect();
+        if due.is_empty() {
+            // sleep until the next control is due, or something else is queued
+            queue = match queue.values.keys().map(wait).min() {
+                Some(timeout) => shared.wake.wait_timeout(queue, timeout).unwrap().0,
+                None => shared.wake.wait(queue).unwrap()
+            };
+            continue;
+        }
+
+        let batch: Vec<(String, ElemValue)> = due.into_iter()
+            .filter_map(|k| queue.values.remove_entry(&k))
+            .collect();
+        // let the ui queue more while this lot is written
+        drop(queue);
+        for (k, v) in batch {
+            let result = backend.set_value(&k, &v).and_then(|_| backend.get_value(&k));
+            last.insert(k.clone(), Instant::now());
+            // nobody's listening any more when flushing on the way out
+            let _ = tx.send((k, result));
+        }
+
+        queue = shared.queue.lock().unwrap();
+        if let Some(notify) = &queue.notify {
+            notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backend::SimulatedBackend, profile::DeviceProfile};
+
+    use super::*;
+
+    fn simulated() -> (SimulatedBackend, &'static DeviceProfile) {
+        let profile = DeviceProfile::default_profile();
+        (SimulatedBackend::new(profile), profile)
+    }
+
+    #[test]
+    fn writes_latest_value() {
+        let (backend, profile) = simulated();
+        let control = profile.global().unwrap();
+        let queue = WriteQueue::new(backend.writer().unwrap());
+        for i in 0..100 {
+            queue.push(control.clone(), ElemValue::Knob { db: -(i as f32), muted: false });
+        }
+        // flushes
+        drop(queue);
+        assert_eq!(backend.get_value(&control), Ok(ElemValue::Knob { db: -99.0, muted: false }));
+    }
+
+    #[test]
+    fn reports_failures() {
+        let (backend, profile) = simulated();
+        let control = profile.capture(0);
+        let queue = WriteQueue::new(backend.writer().unwrap());
+        queue.push(control.clone(), ElemValue::Enum(1000));
+
+        let start = Instant::now();
+        let mut results = Vec::new();
+        while results.is_empty() && start.elapsed() < Duration::from_secs(5) {
+            results = queue.results();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(matches!(&results[..], [(c, Err(DeviceError::OutOfRange { .. }))] if *c == control));
+    }
+}